@@ -4,7 +4,6 @@ use bevy::{
 };
 use bevy_obj::*;
 use bevy_voxel_engine::*;
-use character::CharacterEntity;
 use rand::Rng;
 use std::f32::consts::PI;
 
@@ -45,6 +44,7 @@ fn main() {
 // zero: normal bullet
 // one: orange portal bullet
 // two: blue portal bullet
+// three: explosive bullet
 #[derive(Component)]
 pub struct Bullet {
     bullet_type: u32,
@@ -67,6 +67,9 @@ struct Suzanne;
 #[derive(Component)]
 struct SandSpawner;
 
+#[derive(Component)]
+struct Fountain;
+
 // world space cordinates are in terms of 4 voxels per meter with 0, 0
 // in the world lining up with the center of the voxel world and the edge
 // of the world being half of the world size in each direction
@@ -84,20 +87,17 @@ fn setup(
     for i in 0..2 {
         character_portals[i] = Some(
             commands
-                .spawn((
-                    VoxelizationBundle {
-                        mesh_handle: asset_server.load("models/portal.obj"),
-                        transform: Transform::from_xyz(0.0, 100.0, 0.0)
-                            .looking_at(Vec3::ZERO, Vec3::Y)
-                            .with_scale(Vec3::new(i as f32 * 2.0 - 1.0, 1.0, i as f32 * 2.0 - 1.0)),
-                        voxelization_material: VoxelizationMaterial {
-                            flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
-                            ..default()
-                        },
+                .spawn(VoxelizationBundle {
+                    mesh_handle: asset_server.load("models/portal.obj"),
+                    transform: Transform::from_xyz(0.0, 100.0, 0.0)
+                        .looking_at(Vec3::ZERO, Vec3::Y)
+                        .with_scale(Vec3::new(i as f32 * 2.0 - 1.0, 1.0, i as f32 * 2.0 - 1.0)),
+                    voxelization_material: VoxelizationMaterial {
+                        flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
                         ..default()
                     },
-                    Portal,
-                ))
+                    ..default()
+                })
                 .with_children(|parent| {
                     // portal border
                     parent.spawn(VoxelizationBundle {
@@ -112,6 +112,9 @@ fn setup(
                 .id(),
         );
     }
+    let (portal1, portal2) = (character_portals[0].unwrap(), character_portals[1].unwrap());
+    commands.entity(portal1).insert(Portal::new(portal2));
+    commands.entity(portal2).insert(Portal::new(portal1));
 
     // character
     let character_transform = Transform::from_xyz(0.0, 20.0, -1.0).looking_at(Vec3::ZERO, Vec3::Y);
@@ -126,27 +129,25 @@ fn setup(
                 projection: projection.clone(),
                 ..default()
             },
-            CharacterEntity {
-                in_spectator: false,
-                grounded: false,
-                look_at: -character_transform.local_z(),
-                up: Vec3::new(0.0, 1.0, 0.0),
+            VoxelCharacterBundle {
+                controller: VoxelCharacterController {
+                    look_at: -character_transform.local_z(),
+                    ..default()
+                },
+                ..default()
             },
             CharacterPortals {
                 portal1: character_portals[0].unwrap(),
                 portal2: character_portals[1].unwrap(),
             },
-            VoxelPhysics::new(
-                Vec3::splat(0.0),
-                Vec3::ZERO, // gravity handeled in character.rs
-                CollisionEffect::None,
-            ),
-            BoxCollider {
-                half_size: IVec3::new(2, 4, 2),
-            },
             Tonemapping::SomewhatBoringDisplayTransform,
             BloomSettings::default(),
             Fxaa::default(),
+            VoxelFog {
+                density: 0.0,
+                ..default()
+            },
+            PostProcessSettings::default(),
         ))
         .with_children(|parent| {
             // voxelization preview camera
@@ -181,22 +182,20 @@ fn setup(
 
     // rotated portals
     let pos = vec![Vec3::new(5.0, 0.0, -5.0), Vec3::new(-5.0, 0.0, 5.0)];
+    let mut rotated_portals = Vec::new();
     for i in 0..2 {
-        commands
-            .spawn((
-                VoxelizationBundle {
-                    mesh_handle: asset_server.load("models/portal.obj"),
-                    transform: Transform::from_translation(pos[i])
-                        .looking_at(Vec3::ZERO, Vec3::Y)
-                        .with_scale(Vec3::new(i as f32 * 2.0 - 1.0, 1.0, i as f32 * 2.0 - 1.0)),
-                    voxelization_material: VoxelizationMaterial {
-                        flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
-                        ..default()
-                    },
+        let portal = commands
+            .spawn(VoxelizationBundle {
+                mesh_handle: asset_server.load("models/portal.obj"),
+                transform: Transform::from_translation(pos[i])
+                    .looking_at(Vec3::ZERO, Vec3::Y)
+                    .with_scale(Vec3::new(i as f32 * 2.0 - 1.0, 1.0, i as f32 * 2.0 - 1.0)),
+                voxelization_material: VoxelizationMaterial {
+                    flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
                     ..default()
                 },
-                Portal,
-            ))
+                ..default()
+            })
             .with_children(|parent| {
                 // portal border
                 parent.spawn(VoxelizationBundle {
@@ -207,8 +206,16 @@ fn setup(
                     },
                     ..default()
                 });
-            });
+            })
+            .id();
+        rotated_portals.push(portal);
     }
+    commands
+        .entity(rotated_portals[0])
+        .insert(Portal::new(rotated_portals[1]));
+    commands
+        .entity(rotated_portals[1])
+        .insert(Portal::new(rotated_portals[0]));
 
     // voxelized mesh
     commands.spawn((
@@ -226,13 +233,16 @@ fn setup(
         Suzanne,
     ));
 
-    // sand spawner
+    // sand spawner, pours particles that settle into the world
     commands.spawn((
-        Transform::default(),
-        Box {
-            flags: Flags::NONE,
-            material: 0,
-            half_size: IVec3::new(6, 6, 6),
+        TransformBundle::default(),
+        ParticleEmitter {
+            spawn_rate: 0.0,
+            cone_angle: 0.4,
+            speed: 2.0,
+            lifetime: 10.0,
+            settle: true,
+            ..default()
         },
         SandSpawner,
     ));
@@ -249,9 +259,15 @@ fn shoot(
     mut commands: Commands,
     mouse: Res<Input<MouseButton>>,
     keyboard: Res<Input<KeyCode>>,
-    mut character: Query<(&Transform, &mut CharacterEntity)>,
+    mut character: Query<(
+        Entity,
+        &Transform,
+        &mut VoxelCharacterController,
+        Option<&VoxelSpotLight>,
+    )>,
+    fountains: Query<Entity, With<Fountain>>,
 ) {
-    let (transform, mut character_entity) = character.single_mut();
+    let (entity, transform, mut character_controller, flashlight) = character.single_mut();
 
     if mouse.just_pressed(MouseButton::Left) {
         commands.spawn((
@@ -286,7 +302,12 @@ fn shoot(
 
     if keyboard.just_pressed(KeyCode::F) {
         commands.spawn((
-            Transform::from_translation(transform.translation),
+            TransformBundle::from_transform(Transform::from_translation(transform.translation)),
+            VoxelPointLight {
+                color: Color::ORANGE,
+                intensity: 20.0,
+                range: 8.0,
+            },
             Particle {
                 material: 120,
                 flags: Flags::ANIMATION_FLAG,
@@ -303,8 +324,59 @@ fn shoot(
         ));
     }
 
+    if keyboard.just_pressed(KeyCode::X) {
+        commands.spawn((
+            Transform::from_translation(transform.translation),
+            Particle {
+                material: 120,
+                flags: Flags::ANIMATION_FLAG,
+            },
+            VoxelPhysics::new(
+                -transform.local_z() * 50.0,
+                Vec3::new(0.0, -9.81, 0.0),
+                CollisionEffect::Destroy {
+                    radius: 2.0,
+                    debris: 200,
+                },
+            ),
+            Bullet { bullet_type: 3 },
+        ));
+    }
+
+    if keyboard.just_pressed(KeyCode::G) {
+        if fountains.is_empty() {
+            commands.spawn((
+                TransformBundle::from_transform(*transform),
+                ParticleEmitter {
+                    spawn_rate: 200.0,
+                    cone_angle: 0.2,
+                    speed: 15.0,
+                    material: 8,
+                    ..default()
+                },
+                Fountain,
+            ));
+        } else {
+            for entity in fountains.iter() {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::L) {
+        match flashlight {
+            Some(_) => commands.entity(entity).remove::<VoxelSpotLight>(),
+            None => commands.entity(entity).insert(VoxelSpotLight {
+                range: 30.0,
+                outer_angle: 0.4,
+                inner_angle: 0.3,
+                ..default()
+            }),
+        };
+    }
+
     if keyboard.just_pressed(KeyCode::P) {
-        character_entity.in_spectator = !character_entity.in_spectator;
+        character_controller.flying = !character_controller.flying;
     }
 
     if keyboard.just_pressed(KeyCode::B) {
@@ -341,7 +413,7 @@ fn update_fire(mut particle_query: Query<(Entity, &mut Particle)>, mut commands:
 }
 
 fn update_guns(
-    character_query: Query<&Transform, (With<CharacterEntity>, Without<Gun>)>,
+    character_query: Query<&Transform, (With<VoxelCharacterController>, Without<Gun>)>,
     mut guns: Query<&mut Transform, With<Gun>>,
 ) {
     let character_transform = character_query.single();
@@ -415,23 +487,25 @@ fn spawn_stuff(
 }
 
 fn sand_spawner(
-    mut sand_spawner: Query<(&mut Transform, &mut Box), With<SandSpawner>>,
-    character_query: Query<&Transform, (With<CharacterEntity>, Without<SandSpawner>)>,
+    mut sand_spawner: Query<(&mut Transform, &mut ParticleEmitter), With<SandSpawner>>,
+    character_query: Query<&Transform, (With<VoxelCharacterController>, Without<SandSpawner>)>,
     input: Res<Input<KeyCode>>,
 ) {
     let character = character_query.single();
-    let (mut sand_spawner, mut sand_material) = sand_spawner.single_mut();
+    let (mut sand_spawner, mut emitter) = sand_spawner.single_mut();
 
-    sand_spawner.translation = character.translation - character.local_z() * 10.0;
+    *sand_spawner = Transform::from_translation(character.translation - character.local_z() * 10.0)
+        .looking_to(Vec3::NEG_Y, Vec3::Z);
 
     if input.pressed(KeyCode::R) {
-        sand_material.material = 8;
-        sand_material.flags = Flags::NONE;
+        emitter.spawn_rate = 2000.0;
+        emitter.material = 8;
+        emitter.flags = Flags::COLLISION_FLAG;
     } else if input.pressed(KeyCode::E) {
-        sand_material.material = 100;
-        sand_material.flags = Flags::SAND_FLAG | Flags::COLLISION_FLAG;
+        emitter.spawn_rate = 2000.0;
+        emitter.material = 100;
+        emitter.flags = Flags::SAND_FLAG | Flags::COLLISION_FLAG;
     } else {
-        sand_material.material = 0;
-        sand_material.flags = Flags::NONE;
+        emitter.spawn_rate = 0.0;
     }
 }
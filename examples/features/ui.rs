@@ -1,4 +1,4 @@
-use super::{character::CharacterEntity, Bullet, Particle, VoxelizationPreviewCamera};
+use super::{Bullet, Particle, VoxelizationPreviewCamera};
 use bevy::{
     core_pipeline::{bloom::BloomSettings, fxaa::Fxaa, tonemapping::Tonemapping},
     prelude::*,
@@ -7,7 +7,11 @@ use bevy_egui::{
     egui::{self, Slider},
     EguiContexts, EguiPlugin,
 };
-use bevy_voxel_engine::{Flags, LoadVoxelWorld, RenderGraphSettings, TraceSettings, VoxelPhysics};
+use bevy_voxel_engine::{
+    DenoiseSettings, Flags, LoadVoxelWorld, PostProcessSettings, RenderGraphSettings,
+    ShadowQuality, SkyModel, SunPosition, TraceSettings, VoxelCharacterController, VoxelFog,
+    VoxelPhysics, VoxelSky,
+};
 use rand::Rng;
 
 pub struct UiPlugin;
@@ -21,17 +25,21 @@ impl Plugin for UiPlugin {
 fn ui_system(
     mut commands: Commands,
     mut contexts: EguiContexts,
-    particle_query: Query<Entity, (With<VoxelPhysics>, Without<CharacterEntity>)>,
+    particle_query: Query<Entity, (With<VoxelPhysics>, Without<VoxelCharacterController>)>,
     mut load_voxel_world: ResMut<LoadVoxelWorld>,
     mut render_graph_settings: ResMut<RenderGraphSettings>,
     mut camera_settings_query: Query<(
         &mut TraceSettings,
+        Option<&mut DenoiseSettings>,
         Option<&mut BloomSettings>,
         Option<&mut Tonemapping>,
         Option<&mut Fxaa>,
+        Option<&mut VoxelFog>,
+        Option<&mut PostProcessSettings>,
     )>,
     mut voxelization_preview_camera_query: Query<&mut Camera, With<VoxelizationPreviewCamera>>,
-    mut character_query: Query<&mut CharacterEntity>,
+    mut character_query: Query<&mut VoxelCharacterController>,
+    mut voxel_sky: ResMut<VoxelSky>,
 ) {
     let mut character = character_query.single_mut();
 
@@ -46,19 +54,116 @@ fn ui_system(
                 let path = tinyfiledialogs::open_file_dialog("Select file", "", None);
                 *load_voxel_world = LoadVoxelWorld::File(path.unwrap());
             }
-            for (i, (mut trace_settings, bloom_settings, tonemapping, fxaa)) in
-                camera_settings_query.iter_mut().enumerate()
+            for (
+                i,
+                (
+                    mut trace_settings,
+                    denoise_settings,
+                    bloom_settings,
+                    tonemapping,
+                    fxaa,
+                    fog,
+                    post_process_settings,
+                ),
+            ) in camera_settings_query.iter_mut().enumerate()
             {
                 ui.collapsing(format!("Camera Settings {}", i), |ui| {
                     ui.checkbox(&mut trace_settings.show_ray_steps, "Show ray steps");
                     ui.add(Slider::new(&mut trace_settings.samples, 1..=8).text("Samples"));
-                    ui.checkbox(&mut trace_settings.shadows, "Shadows");
+                    egui::ComboBox::from_label("Shadows")
+                        .selected_text(format!("{:?}", trace_settings.shadows))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut trace_settings.shadows,
+                                ShadowQuality::Off,
+                                "Off",
+                            );
+                            ui.selectable_value(
+                                &mut trace_settings.shadows,
+                                ShadowQuality::Hard,
+                                "Hard",
+                            );
+                            ui.selectable_value(
+                                &mut trace_settings.shadows,
+                                ShadowQuality::Soft,
+                                "Soft",
+                            );
+                        });
+                    ui.add(
+                        Slider::new(&mut trace_settings.shadow_cone_angle, 0.0..=0.2)
+                            .text("Sun angle"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trace_settings.shadow_distance, 0.0..=200.0)
+                            .text("Shadow distance"),
+                    );
+                    ui.checkbox(&mut trace_settings.refraction, "Refraction");
+                    ui.checkbox(&mut trace_settings.reflections, "Reflections");
+                    ui.add(
+                        Slider::new(&mut trace_settings.max_reflection_bounces, 1..=4)
+                            .text("Reflection bounces"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trace_settings.indirect_bounces, 0..=4)
+                            .text("Indirect bounces"),
+                    );
+                    ui.checkbox(&mut trace_settings.cone_tracing, "Cone tracing");
+                    ui.add(
+                        Slider::new(&mut trace_settings.cone_distance, 1.0..=32.0)
+                            .text("Cone distance"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trace_settings.max_portal_depth, 0..=16)
+                            .text("Portal depth"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trace_settings.accumulation_frames, 0..=128)
+                            .text("Accumulation frames"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trace_settings.render_scale, 0.25..=1.0)
+                            .text("Render scale"),
+                    );
+                    ui.add(Slider::new(&mut trace_settings.lod_bias, 0.0..=4.0).text("LOD bias"));
+                    if let Some(mut denoise_settings) = denoise_settings {
+                        ui.checkbox(&mut denoise_settings.enabled, "Denoise");
+                        for (i, pass) in denoise_settings.passes.iter_mut().enumerate() {
+                            ui.add(
+                                Slider::new(&mut pass.colour_phi, 0.0..=0.2)
+                                    .text(format!("Denoise pass {} colour phi", i)),
+                            );
+                        }
+                    }
+                    if let Some(mut fog) = fog {
+                        ui.add(Slider::new(&mut fog.density, 0.0..=0.2).text("Fog density"));
+                        ui.add(
+                            Slider::new(&mut fog.sun_shaft_samples, 0..=32)
+                                .text("Sun shaft samples"),
+                        );
+                    }
                     if let Some(bloom_settings) = bloom_settings {
                         ui.add(
                             Slider::new(&mut bloom_settings.into_inner().intensity, 0.0..=1.0)
                                 .text("Bloom"),
                         );
                     }
+                    if let Some(mut post_process_settings) = post_process_settings {
+                        ui.add(
+                            Slider::new(&mut post_process_settings.exposure, -4.0..=4.0)
+                                .text("Exposure"),
+                        );
+                        ui.add(
+                            Slider::new(&mut post_process_settings.vignette, 0.0..=1.0)
+                                .text("Vignette"),
+                        );
+                        ui.add(
+                            Slider::new(
+                                &mut post_process_settings.chromatic_aberration,
+                                0.0..=0.02,
+                            )
+                            .text("Chromatic aberration"),
+                        );
+                    }
                     if let Some(mut tonemapping) = tonemapping {
                         egui::ComboBox::from_label("")
                             .selected_text(format!("{:?}", tonemapping.as_mut()))
@@ -101,6 +206,24 @@ fn ui_system(
                     }
                 });
             }
+            ui.collapsing("Sky", |ui| {
+                let mut time_of_day = matches!(voxel_sky.sun, SunPosition::TimeOfDay(_));
+                if ui.checkbox(&mut time_of_day, "Time of day").changed() {
+                    *voxel_sky = match time_of_day {
+                        true => VoxelSky {
+                            sun: SunPosition::TimeOfDay(14.0),
+                            model: SkyModel::Procedural,
+                            ..default()
+                        },
+                        false => VoxelSky::default(),
+                    };
+                }
+                if let SunPosition::TimeOfDay(hour) = &mut voxel_sky.sun {
+                    ui.add(Slider::new(hour, 0.0..=24.0).text("Hour"));
+                }
+                ui.add(Slider::new(&mut voxel_sky.sun_intensity, 0.0..=4.0).text("Sun intensity"));
+                ui.add(Slider::new(&mut voxel_sky.ambient, 0.0..=2.0).text("Ambient"));
+            });
             ui.collapsing("Compute", |ui| {
                 if ui.button("spawn particles").clicked() {
                     let mut rng = rand::thread_rng();
@@ -138,10 +261,16 @@ fn ui_system(
                 ui.checkbox(&mut render_graph_settings.clear, "clear");
                 ui.checkbox(&mut render_graph_settings.automata, "automata");
                 ui.checkbox(&mut render_graph_settings.animation, "animation");
+                ui.checkbox(&mut render_graph_settings.particles, "particles");
+                ui.checkbox(&mut render_graph_settings.integrity, "integrity");
+                ui.checkbox(&mut render_graph_settings.navigation, "navigation");
+                ui.checkbox(&mut render_graph_settings.light_volume, "light volume");
+                ui.checkbox(&mut render_graph_settings.mip, "mip");
                 ui.checkbox(&mut render_graph_settings.voxelization, "voxelization");
                 ui.checkbox(&mut render_graph_settings.rebuild, "rebuild");
                 ui.checkbox(&mut render_graph_settings.physics, "physics");
                 ui.checkbox(&mut render_graph_settings.trace, "trace");
+                ui.checkbox(&mut render_graph_settings.denoise, "denoise");
             });
 
             for mut voxelization_preview_camera in voxelization_preview_camera_query.iter_mut() {
@@ -150,14 +279,17 @@ fn ui_system(
                     format!("Preview"),
                 );
             }
-            ui.checkbox(&mut character.in_spectator, "Spectator mode");
+            ui.checkbox(&mut character.flying, "Spectator mode");
 
             // show controls with multi line text
             ui.label("Controls:");
-            ui.label("WASD, Space, Shift - move");
+            ui.label("WASD - move, Space - jump, Shift - crouch");
             ui.label("Escape - toggle cursor");
             ui.label("Left/Right mouse - portals");
             ui.label("F - fireball");
+            ui.label("X - explosive shot");
+            ui.label("G - toggle particle fountain");
+            ui.label("L - toggle flashlight");
             ui.label("P - spectator mode");
             ui.label("B - spawn box");
             ui.label("E - spawn sand");
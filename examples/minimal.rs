@@ -1,7 +1,7 @@
 use bevy::{core_pipeline::fxaa::Fxaa, prelude::*};
 use bevy_voxel_engine::{
-    BevyVoxelEnginePlugin, BoxCollider, CollisionEffect, Edges, Flags, LoadVoxelWorld, Portal,
-    VoxelCameraBundle, VoxelPhysics,
+    BevyVoxelEnginePlugin, BoxCollider, CollisionEffect, Flags, LoadVoxelWorld, Portal,
+    VoxelCameraBundle, VoxelPhysics, VoxelizationBundle, VoxelizationMaterial,
 };
 use std::f32::consts::PI;
 
@@ -19,8 +19,10 @@ struct Cube;
 
 fn setup(
     mut commands: Commands,
+    asset_server: Res<AssetServer>,
     mut load_voxel_world: ResMut<LoadVoxelWorld>,
-    mut _meshes: ResMut<Assets<Mesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     // Voxel world
     *load_voxel_world = LoadVoxelWorld::File("assets/monu9.vox".to_string());
@@ -51,25 +53,41 @@ fn setup(
         Fxaa::default(),
     ));
 
-    // portal pair
-    commands.spawn((
-        Portal,
-        Edges {
-            material: 23,
-            flags: Flags::ANIMATION_FLAG,
-            half_size: IVec3::new(0, 10, 7),
-        },
-        Transform::from_xyz(-5.0, 0.0, -5.0),
-    ));
+    // portal pair, the voxelized portal meshes are what rays and physics go into
+    let mut portals = Vec::new();
+    for (i, z) in [-5.0, 3.0].into_iter().enumerate() {
+        let portal = commands
+            .spawn(VoxelizationBundle {
+                mesh_handle: asset_server.load("models/portal.obj"),
+                transform: Transform::from_xyz(-5.0, 0.0, z)
+                    .looking_at(Vec3::new(0.0, 0.0, z), Vec3::Y)
+                    .with_scale(Vec3::new(i as f32 * 2.0 - 1.0, 1.0, i as f32 * 2.0 - 1.0)),
+                voxelization_material: VoxelizationMaterial {
+                    flags: Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG,
+                    ..default()
+                },
+                ..default()
+            })
+            .id();
+        portals.push(portal);
+    }
+    commands.entity(portals[0]).insert(Portal::new(portals[1]));
+    commands.entity(portals[1]).insert(Portal::new(portals[0]));
+
+    // regular bevy mesh, depth tested against the voxels
     commands.spawn((
-        Portal,
-        Edges {
-            material: 23,
-            flags: Flags::ANIMATION_FLAG,
-            half_size: IVec3::new(0, 10, 7),
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+            material: materials.add(Color::rgb(0.8, 0.2, 0.2).into()),
+            transform: Transform::from_xyz(0.0, 1.0, 0.0),
+            ..default()
         },
-        Transform::from_xyz(-5.0, 0.0, 3.0),
+        Cube,
     ));
+    commands.spawn(DirectionalLightBundle {
+        transform: Transform::from_xyz(1.0, 2.0, 1.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
 }
 
 fn update(mut cube: Query<&mut Transform, With<Cube>>, time: Res<Time>) {
@@ -0,0 +1,236 @@
+use bevy::{
+    core_pipeline::{clear_color::ClearColorConfig, tonemapping::Tonemapping},
+    prelude::*,
+    render::{
+        camera::{RenderTarget, ScalingMode, Viewport},
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+    },
+    window::WindowResized,
+};
+use bevy_voxel_engine::{
+    BevyVoxelEnginePlugin, LoadVoxelWorld, ShadowQuality, TraceSettings, VoxelCameraBundle,
+};
+use std::f32::consts::PI;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(BevyVoxelEnginePlugin)
+        .add_systems(Startup, setup)
+        .add_systems(Update, (set_camera_viewports, move_cameras))
+        .run();
+}
+
+#[derive(Component)]
+struct LeftCamera;
+
+#[derive(Component)]
+struct RightCamera;
+
+#[derive(Component)]
+struct Minimap;
+
+#[derive(Component)]
+struct SecurityCamera;
+
+fn render_image(images: &mut Assets<Image>, width: u32, height: u32) -> Handle<Image> {
+    let size = Extent3d {
+        width,
+        height,
+        ..default()
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    images.add(image)
+}
+
+fn setup(
+    mut commands: Commands,
+    mut load_voxel_world: ResMut<LoadVoxelWorld>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Voxel world
+    *load_voxel_world = LoadVoxelWorld::File("assets/monu9.vox".to_string());
+
+    let projection = Projection::Perspective(PerspectiveProjection {
+        fov: PI / 2.0,
+        ..default()
+    });
+
+    // split screen, both cameras draw into the window inside their viewport
+    commands.spawn((
+        VoxelCameraBundle {
+            transform: Transform::from_xyz(10.0, 5.0, -10.0).looking_at(Vec3::ZERO, Vec3::Y),
+            projection: projection.clone(),
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        LeftCamera,
+    ));
+    commands.spawn((
+        VoxelCameraBundle {
+            camera: Camera {
+                order: 1,
+                hdr: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(-10.0, 5.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
+            projection: projection.clone(),
+            trace_settings: TraceSettings {
+                shadows: ShadowQuality::Soft,
+                samples: 2,
+                ..default()
+            },
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        RightCamera,
+    ));
+
+    // orthographic minimap looking down on the world, rendered before the
+    // window so the ui shows this frame's image
+    let minimap_image = render_image(&mut images, 256, 256);
+    commands.spawn((
+        VoxelCameraBundle {
+            camera: Camera {
+                order: -2,
+                hdr: true,
+                target: RenderTarget::Image(minimap_image.clone()),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 50.0, 0.0).looking_at(Vec3::ZERO, Vec3::NEG_Z),
+            projection: Projection::Orthographic(OrthographicProjection {
+                scaling_mode: ScalingMode::FixedVertical(40.0),
+                far: 100.0,
+                ..default()
+            }),
+            trace_settings: TraceSettings {
+                shadows: ShadowQuality::Off,
+                reflections: false,
+                ..default()
+            },
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        Minimap,
+    ));
+
+    // security camera shown on a screen inside the world, traced at half
+    // resolution
+    let security_image = render_image(&mut images, 512, 288);
+    commands.spawn((
+        VoxelCameraBundle {
+            camera: Camera {
+                order: -1,
+                hdr: true,
+                target: RenderTarget::Image(security_image.clone()),
+                ..default()
+            },
+            transform: Transform::from_xyz(8.0, 8.0, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
+            projection,
+            trace_settings: TraceSettings {
+                render_scale: 0.5,
+                ..default()
+            },
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        SecurityCamera,
+    ));
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::new(3.2, 1.8)))),
+        material: materials.add(StandardMaterial {
+            base_color_texture: Some(security_image),
+            unlit: true,
+            ..default()
+        }),
+        transform: Transform::from_xyz(0.0, 3.0, 0.0),
+        ..default()
+    });
+
+    // the ui is drawn by its own camera over the whole window, it shares the
+    // hdr textures of the voxel cameras so it mustn't tonemap them again
+    commands.spawn(Camera2dBundle {
+        camera: Camera {
+            order: 2,
+            hdr: true,
+            ..default()
+        },
+        camera_2d: Camera2d {
+            clear_color: ClearColorConfig::None,
+        },
+        tonemapping: Tonemapping::None,
+        ..default()
+    });
+    commands.spawn(ImageBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            width: Val::Px(200.0),
+            height: Val::Px(200.0),
+            ..default()
+        },
+        image: UiImage::new(minimap_image),
+        ..default()
+    });
+}
+
+fn set_camera_viewports(
+    windows: Query<&Window>,
+    mut resize_events: EventReader<WindowResized>,
+    mut left_camera: Query<&mut Camera, (With<LeftCamera>, Without<RightCamera>)>,
+    mut right_camera: Query<&mut Camera, With<RightCamera>>,
+) {
+    for resize_event in resize_events.read() {
+        let window = windows.get(resize_event.window).unwrap();
+        let size = UVec2::new(window.physical_width() / 2, window.physical_height());
+
+        left_camera.single_mut().viewport = Some(Viewport {
+            physical_position: UVec2::ZERO,
+            physical_size: size,
+            ..default()
+        });
+        right_camera.single_mut().viewport = Some(Viewport {
+            physical_position: UVec2::new(size.x, 0),
+            physical_size: size,
+            ..default()
+        });
+    }
+}
+
+fn move_cameras(
+    time: Res<Time>,
+    mut right_camera: Query<&mut Transform, (With<RightCamera>, Without<SecurityCamera>)>,
+    mut security_camera: Query<&mut Transform, (With<SecurityCamera>, Without<RightCamera>)>,
+) {
+    let t = time.elapsed_seconds();
+
+    // orbit the right camera around the middle of the world
+    let mut transform = right_camera.single_mut();
+    *transform = Transform::from_xyz(14.0 * (t * 0.2).cos(), 5.0, 14.0 * (t * 0.2).sin())
+        .looking_at(Vec3::ZERO, Vec3::Y);
+
+    // pan the security camera back and forth
+    let mut transform = security_camera.single_mut();
+    transform.rotation =
+        Quat::from_rotation_y(0.8 * (t * 0.5).sin() + PI / 4.0) * Quat::from_rotation_x(-0.5);
+}
@@ -0,0 +1,220 @@
+use crate::{BoxCollider, CollisionEffect, VoxelPhysics, VOXELS_PER_METER};
+use bevy::prelude::*;
+
+pub struct CharacterPlugin;
+
+impl Plugin for CharacterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_character_controller);
+    }
+}
+
+/// First person controller that walks around on the voxel world. It is driven
+/// by the `VoxelCharacterAction` on the same entity, so players and AI agents
+/// can control it the same way.
+#[derive(Component)]
+pub struct VoxelCharacterController {
+    /// fly around freely instead of walking, jump and crouch move up and down
+    pub flying: bool,
+    pub grounded: bool,
+    pub crouched: bool,
+    /// set by the physics pass, false while something above a crouched
+    /// character keeps it from standing up
+    pub can_stand: bool,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    pub speed: f32,
+    pub crouch_speed: f32,
+    pub jump_speed: f32,
+    /// half size of the collider while standing
+    pub half_size: IVec3,
+    /// half height of the collider while crouching
+    pub crouch_half_height: i32,
+    /// highest ledge in voxels that is walked onto without jumping
+    pub step_height: u32,
+}
+
+impl Default for VoxelCharacterController {
+    fn default() -> Self {
+        Self {
+            flying: false,
+            grounded: false,
+            crouched: false,
+            can_stand: true,
+            look_at: Vec3::NEG_Z,
+            up: Vec3::Y,
+            speed: 10.0,
+            crouch_speed: 4.0,
+            jump_speed: 10.0,
+            half_size: IVec3::new(2, 4, 2),
+            crouch_half_height: 2,
+            step_height: 2,
+        }
+    }
+}
+
+/// What a `VoxelCharacterController` should do this frame
+#[derive(Component, Clone, Default)]
+pub struct VoxelCharacterAction {
+    /// x is right and y is forward, normalized if longer than 1
+    pub movement: Vec2,
+    /// yaw and pitch in radians, reset once it has been applied
+    pub look: Vec2,
+    pub jump: bool,
+    pub crouch: bool,
+}
+
+#[derive(Bundle)]
+pub struct VoxelCharacterBundle {
+    pub controller: VoxelCharacterController,
+    pub action: VoxelCharacterAction,
+    pub voxel_physics: VoxelPhysics,
+    pub box_collider: BoxCollider,
+}
+
+impl Default for VoxelCharacterBundle {
+    fn default() -> Self {
+        let controller = VoxelCharacterController::default();
+        Self {
+            box_collider: BoxCollider {
+                half_size: controller.half_size,
+            },
+            controller,
+            action: default(),
+            // gravity is handled by the controller
+            voxel_physics: VoxelPhysics::new(Vec3::ZERO, Vec3::ZERO, CollisionEffect::None),
+        }
+    }
+}
+
+fn update_character_controller(
+    mut character_query: Query<(
+        &mut Transform,
+        &mut VoxelPhysics,
+        &mut BoxCollider,
+        &mut VoxelCharacterController,
+        &mut VoxelCharacterAction,
+    )>,
+    time: Res<Time>,
+) {
+    for (mut transform, mut voxel_physics, mut box_collider, mut controller, mut action) in
+        character_query.iter_mut()
+    {
+        // follow the rotation of any portal walked through since the last frame
+        controller.look_at = voxel_physics.portal_rotation * controller.look_at;
+        controller.up = voxel_physics.portal_rotation * controller.up;
+        voxel_physics.portal_rotation = Mat3::IDENTITY;
+
+        // rotation
+        if action.look != Vec2::ZERO {
+            let angle = controller.look_at.dot(controller.up).acos();
+            let max_angle = 0.01;
+
+            // Order is important to prevent unintended roll
+            controller.look_at = Quat::from_axis_angle(Vec3::Y, -action.look.x)
+                * Quat::from_axis_angle(
+                    transform.local_x(),
+                    (-action.look.y)
+                        .min(angle - max_angle)
+                        .max(angle + max_angle - std::f32::consts::PI),
+                )
+                * controller.look_at;
+            action.look = Vec2::ZERO;
+        }
+
+        let pos = transform.translation;
+        transform.look_at(pos + controller.look_at, controller.up);
+
+        // crouching keeps the feet in place and lowers the head, standing up
+        // waits until there is room for it
+        controller.crouched =
+            (action.crouch && !controller.flying) || (controller.crouched && !controller.can_stand);
+        let half_height = match controller.crouched {
+            true => controller.crouch_half_height,
+            false => controller.half_size.y,
+        };
+        if box_collider.half_size.y != half_height {
+            let difference = half_height - box_collider.half_size.y;
+            transform.translation.y += difference as f32 / VOXELS_PER_METER;
+        }
+        box_collider.half_size =
+            IVec3::new(controller.half_size.x, half_height, controller.half_size.z);
+
+        // Movement
+        // only the horizontal part is normalized so flying up or down doesn't
+        // slow down the movement
+        let movement = action.movement.clamp_length_max(1.0);
+        let mut input = Vec3::new(
+            movement.x,
+            (action.jump as i32 - action.crouch as i32) as f32,
+            -movement.y,
+        );
+
+        input *= match controller.crouched {
+            true => controller.crouch_speed,
+            false => controller.speed,
+        };
+
+        let target_velocity = if controller.flying {
+            input.z * transform.local_z()
+                + input.x * transform.local_x()
+                + input.y * transform.local_y()
+        } else {
+            if voxel_physics.velocity.y == 0.0 {
+                controller.grounded = true;
+            }
+
+            if action.jump && controller.grounded {
+                voxel_physics.velocity.y = controller.jump_speed;
+                controller.grounded = false;
+            }
+
+            let gravity = match voxel_physics.velocity.y > 0.0 {
+                true => -9.81,
+                false => -15.0,
+            };
+            voxel_physics.velocity += gravity * time.delta_seconds() * Vec3::Y;
+
+            let plane_forward = transform.local_x().cross(Vec3::Y).normalize();
+
+            input.z * plane_forward
+                + input.x * transform.local_x()
+                + voxel_physics.velocity.y * Vec3::Y
+        };
+
+        let acceleration: f32 = if controller.flying || controller.grounded {
+            0.2
+        } else {
+            0.01
+        };
+
+        voxel_physics.velocity = lerp(
+            voxel_physics.velocity,
+            target_velocity,
+            acceleration,
+            time.delta_seconds(),
+        );
+
+        controller.up = slerp(
+            controller.up.normalize(),
+            Vec3::Y,
+            0.04,
+            time.delta_seconds(),
+        );
+    }
+}
+
+fn lerp(i: Vec3, f: Vec3, s: f32, dt: f32) -> Vec3 {
+    let s = (1.0 - s).powf(dt * 120.0);
+    i * s + f * (1.0 - s)
+}
+
+// https://youtu.be/ibkT5ao8kGY
+fn slerp(i: Vec3, f: Vec3, s: f32, dt: f32) -> Vec3 {
+    let s = (1.0 - s).powf(dt * 120.0);
+    let theta = i.dot(f).acos();
+    if theta.sin() == 0.0 {
+        return i + Vec3::splat(0.00000001);
+    }
+    ((s * theta).sin() / theta.sin()) * i + (((1.0 - s) * theta).sin() / theta.sin()) * f
+}
@@ -1,16 +1,33 @@
 use bevy::{
+    core_pipeline::{clear_color::ClearColorConfig, core_3d::Camera3dDepthLoadOp},
     prelude::*,
-    render::{camera::CameraRenderGraph, primitives::Frustum, view::VisibleEntities},
+    render::{
+        camera::CameraRenderGraph, extract_resource::ExtractResource, primitives::Frustum,
+        view::VisibleEntities,
+    },
 };
+use character::CharacterPlugin;
+pub use character::{VoxelCharacterAction, VoxelCharacterBundle, VoxelCharacterController};
+use navigation::NavigationPlugin;
+pub use navigation::{NavigationAgent, NavigationGrid};
 use physics::PhysicsPlugin;
 pub use physics::VOXELS_PER_METER;
 use voxel_pipeline::RenderPlugin;
 pub use voxel_pipeline::{
-    trace::TraceSettings, voxelization::VoxelizationMaterial,
-    voxelization::VoxelizationMaterialType, RenderGraphSettings,
+    denoise::{DenoisePassData, DenoiseSettings},
+    lights::{VoxelPointLight, VoxelSpotLight},
+    post_process::PostProcessSettings,
+    trace::{
+        ShadowQuality, SkyModel, SunPosition, TraceSettings, VoxelFog, VoxelRender, VoxelSky,
+    },
+    voxelization::VoxelizationMaterial,
+    voxelization::VoxelizationMaterialType,
+    RenderGraphSettings,
 };
 
+mod character;
 mod load;
+mod navigation;
 mod physics;
 mod voxel_pipeline;
 
@@ -20,9 +37,28 @@ pub struct Particle {
     pub flags: u8,
 }
 
-/// normal must be a normalized voxel normal
-#[derive(Component)]
-pub struct Portal;
+/// Sends rays and physics objects that go into the voxelized mesh out of the
+/// `target` entity. The mesh needs `Flags::PORTAL_FLAG`, and its voxels get a
+/// portal index instead of their material, portals without a
+/// `VoxelizationMaterial` do nothing. Two portals targeting each other
+/// make a pair, a target that doesn't lead back makes a one way portal. The
+/// portal faces along `-local_z` and its normal must be a voxel normal.
+#[derive(Component, Clone)]
+pub struct Portal {
+    /// where things come out, any entity with a `GlobalTransform`
+    pub target: Entity,
+    /// disabled portals, like the far side of one way portals, are see through
+    pub enabled: bool,
+}
+
+impl Portal {
+    pub fn new(target: Entity) -> Self {
+        Self {
+            target,
+            enabled: true,
+        }
+    }
+}
 
 #[derive(Component)]
 pub struct Edges {
@@ -61,8 +97,11 @@ impl VoxelPhysics {
 
 pub enum CollisionEffect {
     None,
+    /// `debris` is the maximum number of destroyed voxels that get turned
+    /// into gpu particles, 0 means the voxels just vanish
     Destroy {
         radius: f32,
+        debris: u32,
     },
     Place {
         radius: f32,
@@ -85,12 +124,13 @@ impl CollisionEffect {
             CollisionEffect::SetFlags { .. } => 3u32 as f32,
         };
         vec.y = match self {
-            CollisionEffect::Destroy { radius }
+            CollisionEffect::Destroy { radius, .. }
             | CollisionEffect::Place { radius, .. }
             | CollisionEffect::SetFlags { radius, .. } => *radius,
             _ => 0.0,
         };
         vec.z = match self {
+            CollisionEffect::Destroy { debris, .. } => bytemuck::cast(*debris),
             CollisionEffect::Place {
                 material, flags, ..
             } => bytemuck::cast(*material as u32 | ((*flags as u32) << 8)),
@@ -102,11 +142,99 @@ impl CollisionEffect {
     }
 }
 
+/// Spawns particles that only exist on the gpu. They are shot out of the
+/// emitters forward direction and drawn into the world every frame.
+#[derive(Component)]
+pub struct ParticleEmitter {
+    /// particles per second
+    pub spawn_rate: f32,
+    /// half angle of the cone particles are shot out in, in radians
+    pub cone_angle: f32,
+    pub speed: f32,
+    /// seconds before a particle settles or disappears
+    pub lifetime: f32,
+    pub material: u8,
+    pub flags: u8,
+    pub gravity: Vec3,
+    /// applied when a particle hits a voxel, the particle is removed afterwards
+    pub collision_effect: CollisionEffect,
+    /// place the particle into the world as a voxel when it comes to rest
+    pub settle: bool,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        Self {
+            spawn_rate: 100.0,
+            cone_angle: 0.3,
+            speed: 10.0,
+            lifetime: 2.0,
+            material: 100,
+            flags: Flags::COLLISION_FLAG,
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            collision_effect: CollisionEffect::None,
+            settle: false,
+        }
+    }
+}
+
+/// Controls the particles spawned by `CollisionEffect::Destroy`
+#[derive(Resource, Clone, ExtractResource)]
+pub struct DebrisSettings {
+    /// seconds before a debris particle settles or disappears
+    pub lifetime: f32,
+    pub speed: f32,
+    pub gravity: Vec3,
+    /// place the debris back into the world as voxels when it comes to
+    /// rest instead of removing it
+    pub settle: bool,
+}
+
+impl Default for DebrisSettings {
+    fn default() -> Self {
+        Self {
+            lifetime: 3.0,
+            speed: 8.0,
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            settle: true,
+        }
+    }
+}
+
+/// How voxels that lose their connection to the ground or an
+/// `Flags::ANCHOR_FLAG` voxel fall down
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Collapse {
+    /// flag the voxels as sand so the automata pass drops them
+    Sand,
+    /// turn the voxels into particles using the `DebrisSettings`
+    Debris,
+}
+
+/// Controls the structural integrity pass that runs around destroyed voxels
+#[derive(Resource, Clone, ExtractResource)]
+pub struct IntegritySettings {
+    pub collapse: Collapse,
+}
+
+impl Default for IntegritySettings {
+    fn default() -> Self {
+        Self {
+            collapse: Collapse::Sand,
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct BoxCollider {
     pub half_size: IVec3,
 }
 
+/// A camera that traces the voxel world. Any number of them can be spawned,
+/// each with its own `TraceSettings`, a viewport for split screen or an image
+/// as the target. Cameras drawing into the same target share its hdr
+/// textures, and bevy's tonemapping and fxaa run over the whole of them, so
+/// those only belong on the last camera of a target.
 #[derive(Bundle)]
 pub struct VoxelCameraBundle {
     pub camera: Camera,
@@ -118,6 +246,7 @@ pub struct VoxelCameraBundle {
     pub global_transform: GlobalTransform,
     pub camera_3d: Camera3d,
     pub trace_settings: TraceSettings,
+    pub denoise_settings: DenoiseSettings,
 }
 
 impl Default for VoxelCameraBundle {
@@ -133,8 +262,14 @@ impl Default for VoxelCameraBundle {
             frustum: default(),
             transform: default(),
             global_transform: default(),
-            camera_3d: default(),
+            // keep the traced colour and depth for the mesh passes
+            camera_3d: Camera3d {
+                clear_color: ClearColorConfig::None,
+                depth_load_op: Camera3dDepthLoadOp::Load,
+                ..default()
+            },
             trace_settings: default(),
+            denoise_settings: default(),
         }
     }
 }
@@ -156,6 +291,8 @@ impl Plugin for BevyVoxelEnginePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(Msaa::Off)
             .add_plugins(PhysicsPlugin)
+            .add_plugins(CharacterPlugin)
+            .add_plugins(NavigationPlugin)
             .add_plugins(RenderPlugin);
     }
 }
@@ -174,5 +311,6 @@ pub mod Flags {
     pub const ANIMATION_FLAG: u8 = 32; // 0b00100000
     pub const COLLISION_FLAG: u8 = 16; // 0b00010000
     pub const SAND_FLAG: u8 = 8; // 0b00001000
+    pub const ANCHOR_FLAG: u8 = 4; // 0b00000100
     pub const NONE: u8 = 0; // 0b00000000
 }
@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::Flags;
+use crate::{voxel_pipeline::voxel_world::VoxelMaterial, Flags};
 
 #[derive(Clone)]
 pub struct GH {
@@ -8,6 +8,7 @@ pub struct GH {
     pub texture_size: u32,
     pub texture_data: Vec<u8>,
     pub pallete: Pallete,
+    pub materials: [VoxelMaterial; 256],
 }
 
 #[derive(Clone, Deref, DerefMut)]
@@ -26,6 +27,7 @@ impl GH {
             texture_size,
             texture_data: vec![0; (texture_size * texture_size * texture_size * 2) as usize],
             pallete: Pallete([[0.0; 4]; 256]),
+            materials: [VoxelMaterial::default(); 256],
         }
     }
 
@@ -68,26 +70,17 @@ impl GH {
 
         for i in 0..256 {
             let colour = vox.palette[i];
-            let mut material = Vec4::new(
+            let colour = Vec4::new(
                 colour.r as f32 / 255.0,
                 colour.g as f32 / 255.0,
                 colour.b as f32 / 255.0,
                 0.0,
             );
-            material = material.powf(2.2);
+            gh.pallete[i] = colour.powf(2.2).to_array();
 
             if let Some(vox_material) = vox.materials.get(i) {
-                let vox_material = vox_material.properties.clone();
-                if vox_material["_type"] == "_emit" {
-                    material *= 1.0 + vox_material["_emit"].parse::<f32>().unwrap();
-                    if vox_material.contains_key("_flux") {
-                        material = material.powf(vox_material["_flux"].parse::<f32>().unwrap());
-                    }
-                    material.w = 1.0;
-                }
+                gh.materials[i] = Self::material_from_vox(&vox_material.properties);
             }
-
-            gh.pallete[i] = material.to_array();
         }
 
         for voxel in &vox.models[0].voxels {
@@ -106,6 +99,38 @@ impl GH {
         Ok(gh)
     }
 
+    fn material_from_vox(properties: &dot_vox::Dict) -> VoxelMaterial {
+        let property = |name: &str| {
+            properties
+                .get(name)
+                .and_then(|value| value.parse::<f32>().ok())
+        };
+
+        let mut material = VoxelMaterial::default();
+        let material_type = properties.get("_type").map(String::as_str);
+
+        if matches!(material_type, Some("_metal") | Some("_blend")) {
+            material.metallic = property("_metal").unwrap_or(0.0);
+            material.roughness = property("_rough").unwrap_or(material.roughness);
+        }
+        if matches!(material_type, Some("_glass") | Some("_blend")) {
+            material.transparency = property("_trans").unwrap_or(0.0);
+            material.roughness = property("_rough").unwrap_or(material.roughness);
+            // magicavoxel stores the index of refraction minus 1
+            if let Some(ior) = property("_ior") {
+                material.ior = 1.0 + ior;
+            }
+        }
+        if matches!(material_type, Some("_emit") | Some("_blend")) {
+            material.emission = 1.0 + property("_emit").unwrap_or(0.0);
+            if let Some(flux) = property("_flux") {
+                material.emission = material.emission.powf(flux);
+            }
+        }
+
+        material
+    }
+
     fn next_power_of_2(number: u32) -> u32 {
         let mut n = number;
 
@@ -0,0 +1,490 @@
+use crate::{
+    voxel_pipeline::{
+        compute::{NavigationData, NAVIGATION_CHUNK_WORDS, READBACK_IDLE, READBACK_MAPPED},
+        voxel_world::{ExtractedPortal, NewGH, VoxelPortals, VoxelUniforms},
+    },
+    RenderGraphSettings, VOXELS_PER_METER,
+};
+use bevy::{prelude::*, render::renderer::RenderDevice, utils::HashMap};
+use std::{cmp::Reverse, collections::BinaryHeap, sync::atomic::Ordering};
+
+const CHUNK_SIZE: i32 = 8;
+// how far from the center of a portal walkable voxels are linked through it
+const PORTAL_LINK_RADIUS: i32 = 8;
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(NavigationGrid::new(0, 0))
+            .add_systems(PreUpdate, insert_navigation_data)
+            .add_systems(PostUpdate, count_voxel_worlds);
+    }
+}
+
+/// The collision voxels of the world, kept up to date by the navigation pass
+/// when `RenderGraphSettings::navigation` is enabled. Changed parts of the
+/// world are streamed back from the gpu a few chunks at a time.
+#[derive(Resource)]
+pub struct NavigationGrid {
+    size: u32,
+    world: u32,
+    chunks: Vec<[u32; NAVIGATION_CHUNK_WORDS]>,
+    portals: Vec<ExtractedPortal>,
+}
+
+/// The size and movement of the agent a path is found for, in voxels
+#[derive(Clone, Copy)]
+pub struct NavigationAgent {
+    pub height: u32,
+    pub step_height: u32,
+    pub max_drop: u32,
+    /// the most voxels that are looked at before giving up
+    pub max_search: usize,
+}
+
+impl Default for NavigationAgent {
+    fn default() -> Self {
+        Self {
+            height: 9,
+            step_height: 2,
+            max_drop: 8,
+            max_search: 100000,
+        }
+    }
+}
+
+impl NavigationGrid {
+    fn new(size: u32, world: u32) -> Self {
+        let chunks_per_side = (size / CHUNK_SIZE as u32) as usize;
+        Self {
+            size,
+            world,
+            chunks: vec![[0; NAVIGATION_CHUNK_WORDS]; chunks_per_side.pow(3)],
+            portals: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn world_to_voxel(&self, world_pos: Vec3) -> IVec3 {
+        (world_pos * VOXELS_PER_METER).floor().as_ivec3() + IVec3::splat(self.size as i32 / 2)
+    }
+
+    /// The center of the voxel in world space
+    pub fn voxel_to_world(&self, pos: IVec3) -> Vec3 {
+        ((pos - IVec3::splat(self.size as i32 / 2)).as_vec3() + 0.5) / VOXELS_PER_METER
+    }
+
+    pub fn is_solid(&self, pos: IVec3) -> bool {
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(IVec3::splat(self.size as i32)).any() {
+            return false;
+        }
+
+        let chunks_per_side = self.size as i32 / CHUNK_SIZE;
+        let chunk = pos / CHUNK_SIZE;
+        let chunk_index =
+            chunk.x + chunk.y * chunks_per_side + chunk.z * chunks_per_side * chunks_per_side;
+
+        let local = pos % CHUNK_SIZE;
+        let bit = (local.x + local.y * CHUNK_SIZE + local.z * CHUNK_SIZE * CHUNK_SIZE) as usize;
+        self.chunks[chunk_index as usize][bit / 32] & (1 << (bit % 32)) != 0
+    }
+
+    /// Whether the agent can stand in the voxel, on top of a solid voxel and
+    /// with room for its height
+    pub fn is_walkable(&self, pos: IVec3, agent: &NavigationAgent) -> bool {
+        self.is_solid(pos - IVec3::Y) && self.is_clear(pos, agent.height as i32)
+    }
+
+    fn is_clear(&self, pos: IVec3, height: i32) -> bool {
+        (0..height).all(|i| !self.is_solid(pos + IVec3::Y * i))
+    }
+
+    /// Finds the closest walkable voxel at or below the position
+    pub fn ground(&self, pos: IVec3, agent: &NavigationAgent) -> Option<IVec3> {
+        let depth = (agent.height + agent.max_drop) as i32;
+        (0..=depth)
+            .map(|i| pos - IVec3::Y * i)
+            .find(|pos| self.is_walkable(*pos, agent))
+    }
+
+    /// A* from `start` to `goal` in world space. The returned path is the
+    /// world space centers of the voxels the agent stands in, portals are
+    /// used if they are shorter.
+    pub fn find_path(&self, start: Vec3, goal: Vec3, agent: &NavigationAgent) -> Option<Vec<Vec3>> {
+        let start = self.ground(self.world_to_voxel(start), agent)?;
+        let goal = self.ground(self.world_to_voxel(goal), agent)?;
+
+        let (path, _) = self.find_voxel_path(start, goal, agent)?;
+        Some(
+            path.into_iter()
+                .map(|pos| self.voxel_to_world(pos))
+                .collect(),
+        )
+    }
+
+    // A* between two walkable voxels, returns the voxels of the path and its cost
+    fn find_voxel_path(
+        &self,
+        start: IVec3,
+        goal: IVec3,
+        agent: &NavigationAgent,
+    ) -> Option<(Vec<IVec3>, u32)> {
+        let portal_links = self.portal_links(agent);
+
+        // the least a path from each link's entry to the goal can cost, going
+        // through any number of portals on the way
+        let mut link_costs: Vec<(IVec3, IVec3, u32)> = portal_links
+            .iter()
+            .map(|(entry, exit)| (*entry, *exit, STRAIGHT_COST + distance(*exit, goal)))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..link_costs.len() {
+                let exit = link_costs[i].1;
+                let through = link_costs
+                    .iter()
+                    .map(|(entry, _, cost)| STRAIGHT_COST + distance(exit, *entry) + cost)
+                    .min()
+                    .unwrap();
+                if through < link_costs[i].2 {
+                    link_costs[i].2 = through;
+                    changed = true;
+                }
+            }
+        }
+
+        let heuristic = |pos: IVec3| {
+            link_costs
+                .iter()
+                .map(|(entry, _, cost)| distance(pos, *entry) + cost)
+                .fold(distance(pos, goal), u32::min)
+        };
+
+        let mut open = BinaryHeap::new();
+        let mut visited: HashMap<IVec3, (u32, IVec3)> = HashMap::new();
+
+        open.push(Reverse((heuristic(start), 0, start.x, start.y, start.z)));
+        visited.insert(start, (0, start));
+
+        while let Some(Reverse((_, cost, x, y, z))) = open.pop() {
+            let pos = IVec3::new(x, y, z);
+
+            // a shorter way here was found after this one was queued
+            if cost > visited[&pos].0 {
+                continue;
+            }
+
+            if pos == goal {
+                let mut path = vec![pos];
+                let mut current = pos;
+                while current != start {
+                    current = visited[&current].1;
+                    path.push(current);
+                }
+                path.reverse();
+                return Some((path, cost));
+            }
+
+            if visited.len() > agent.max_search {
+                return None;
+            }
+
+            let mut neighbours = self.neighbours(pos, agent);
+            if let Some(exit) = portal_links.get(&pos) {
+                neighbours.push((*exit, STRAIGHT_COST));
+            }
+
+            for (neighbour, move_cost) in neighbours {
+                let new_cost = cost + move_cost;
+                let shorter = match visited.get(&neighbour) {
+                    Some((old_cost, _)) => new_cost < *old_cost,
+                    None => true,
+                };
+                if shorter {
+                    visited.insert(neighbour, (new_cost, pos));
+                    open.push(Reverse((
+                        new_cost + heuristic(neighbour),
+                        new_cost,
+                        neighbour.x,
+                        neighbour.y,
+                        neighbour.z,
+                    )));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn neighbours(&self, pos: IVec3, agent: &NavigationAgent) -> Vec<(IVec3, u32)> {
+        let height = agent.height as i32;
+        let mut neighbours = Vec::new();
+
+        for (dx, dz) in [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ] {
+            // prefer staying level, then stepping up, then dropping down
+            let heights = std::iter::once(0)
+                .chain(1..=agent.step_height as i32)
+                .chain((1..=agent.max_drop as i32).map(|dy| -dy));
+
+            for dy in heights {
+                let next = pos + IVec3::new(dx, dy, dz);
+                if !self.is_walkable(next, agent) {
+                    continue;
+                }
+
+                // room to step up or to fall down
+                let top = pos.y.max(next.y);
+                if !self.is_clear(pos, top - pos.y + height)
+                    || !self.is_clear(next, top - next.y + height)
+                {
+                    continue;
+                }
+
+                // don't cut corners
+                let diagonal = dx != 0 && dz != 0;
+                if diagonal
+                    && (!self.is_clear(IVec3::new(pos.x + dx, top, pos.z), height)
+                        || !self.is_clear(IVec3::new(pos.x, top, pos.z + dz), height))
+                {
+                    continue;
+                }
+
+                let move_cost = match diagonal {
+                    true => DIAGONAL_COST,
+                    false => STRAIGHT_COST,
+                };
+                neighbours.push((next, move_cost + STRAIGHT_COST * dy.unsigned_abs()));
+                break;
+            }
+        }
+
+        neighbours
+    }
+
+    // Links every walkable voxel next to a portal to where it comes out on the
+    // other side
+    fn portal_links(&self, agent: &NavigationAgent) -> HashMap<IVec3, IVec3> {
+        let mut links = HashMap::new();
+
+        for portal in self.portals.iter() {
+            let center = self.world_to_voxel(portal.position);
+
+            for x in -PORTAL_LINK_RADIUS..=PORTAL_LINK_RADIUS {
+                for y in -PORTAL_LINK_RADIUS..=PORTAL_LINK_RADIUS {
+                    for z in -PORTAL_LINK_RADIUS..=PORTAL_LINK_RADIUS {
+                        let pos = center + IVec3::new(x, y, z);
+                        let world_pos = self.voxel_to_world(pos);
+                        let distance = (world_pos - portal.position).dot(portal.normal);
+                        if distance.abs() > 1.0 / VOXELS_PER_METER || !self.is_walkable(pos, agent)
+                        {
+                            continue;
+                        }
+
+                        // step through the portal plane, then into the other portal
+                        let through = world_pos - 2.0 * distance * portal.normal;
+                        let exit =
+                            self.world_to_voxel(portal.transformation.transform_point3(through));
+                        if let Some(exit) =
+                            self.ground(exit + IVec3::Y * agent.step_height as i32, agent)
+                        {
+                            links.insert(pos, exit);
+                        }
+                    }
+                }
+            }
+        }
+
+        links
+    }
+}
+
+// octile distance in the xz plane plus the height difference
+fn distance(a: IVec3, b: IVec3) -> u32 {
+    let d = (a - b).abs();
+    let (long, short) = (d.x.max(d.z) as u32, d.x.min(d.z) as u32);
+    STRAIGHT_COST * (long - short) + DIAGONAL_COST * short + STRAIGHT_COST * d.y as u32
+}
+
+fn count_voxel_worlds(new_gh: Res<NewGH>, mut navigation_data: ResMut<NavigationData>) {
+    if let NewGH::Some(_) = new_gh.as_ref() {
+        navigation_data.world = navigation_data.world.wrapping_add(1);
+    }
+}
+
+fn insert_navigation_data(
+    mut navigation_grid: ResMut<NavigationGrid>,
+    navigation_data: Res<NavigationData>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    voxel_portals: Res<VoxelPortals>,
+    render_device: Res<RenderDevice>,
+    render_graph_settings: Res<RenderGraphSettings>,
+) {
+    if !render_graph_settings.navigation {
+        return;
+    }
+
+    // a new world gets sent from scratch
+    if navigation_grid.world != navigation_data.world
+        || navigation_grid.size != voxel_uniforms.texture_size
+    {
+        *navigation_grid = NavigationGrid::new(voxel_uniforms.texture_size, navigation_data.world);
+    }
+
+    navigation_grid.portals = voxel_portals
+        .portals
+        .iter()
+        // disabled portals have no normal
+        .filter(|portal| portal.normal != Vec3::ZERO)
+        .copied()
+        .collect();
+
+    // Process the navigation data once the gpu is done with it, without
+    // waiting for it
+    render_device.poll(wgpu::Maintain::Poll);
+    if navigation_data.readback.load(Ordering::Acquire) != READBACK_MAPPED {
+        return;
+    }
+
+    // chunks read before the world changed don't belong in the new grid
+    if navigation_data.readback_world.load(Ordering::Relaxed) == navigation_grid.world {
+        let navigation_buffer_slice = navigation_data.navigation_buffer_cpu.slice(..);
+        let data = navigation_buffer_slice.get_mapped_range();
+        let result: &[u32] = bytemuck::cast_slice(&data);
+
+        let stride = NAVIGATION_CHUNK_WORDS + 1;
+        let count = (result[0] as usize).min((result.len() - 1) / stride);
+        for i in 0..count {
+            let update = &result[1 + i * stride..1 + (i + 1) * stride];
+            if let Some(chunk) = navigation_grid.chunks.get_mut(update[0] as usize) {
+                chunk.copy_from_slice(&update[1..]);
+            }
+        }
+    }
+
+    navigation_data.navigation_buffer_cpu.unmap();
+    navigation_data
+        .readback
+        .store(READBACK_IDLE, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 32;
+
+    // a world with a floor at y = 0, so the agent walks at y = 1
+    fn floor_grid() -> NavigationGrid {
+        let mut grid = NavigationGrid::new(SIZE as u32, 0);
+        for x in 0..SIZE {
+            for z in 0..SIZE {
+                set_solid(&mut grid, IVec3::new(x, 0, z));
+            }
+        }
+        grid
+    }
+
+    fn set_solid(grid: &mut NavigationGrid, pos: IVec3) {
+        let chunks_per_side = SIZE / CHUNK_SIZE;
+        let chunk = pos / CHUNK_SIZE;
+        let chunk_index =
+            chunk.x + chunk.y * chunks_per_side + chunk.z * chunks_per_side * chunks_per_side;
+
+        let local = pos % CHUNK_SIZE;
+        let bit = (local.x + local.y * CHUNK_SIZE + local.z * CHUNK_SIZE * CHUNK_SIZE) as usize;
+        grid.chunks[chunk_index as usize][bit / 32] |= 1 << (bit % 32);
+    }
+
+    // a full height wall across the world at x = 16, up to but not including z
+    fn wall(grid: &mut NavigationGrid, z: i32) {
+        for y in 1..SIZE {
+            for z in 0..z {
+                set_solid(grid, IVec3::new(16, y, z));
+            }
+        }
+    }
+
+    #[test]
+    fn straight_path() {
+        let grid = floor_grid();
+        let agent = NavigationAgent::default();
+
+        // ground drops both ends onto the floor
+        let start = grid.voxel_to_world(IVec3::new(4, 5, 8));
+        let goal = grid.voxel_to_world(IVec3::new(12, 3, 8));
+        let path = grid.find_path(start, goal, &agent).unwrap();
+
+        let expected: Vec<Vec3> = (4..=12)
+            .map(|x| grid.voxel_to_world(IVec3::new(x, 1, 8)))
+            .collect();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn blocked_path() {
+        let mut grid = floor_grid();
+        wall(&mut grid, SIZE);
+        let agent = NavigationAgent::default();
+
+        let path = grid.find_voxel_path(IVec3::new(8, 1, 8), IVec3::new(24, 1, 8), &agent);
+        assert!(path.is_none());
+    }
+
+    #[test]
+    fn diagonal_path() {
+        let grid = floor_grid();
+        let agent = NavigationAgent::default();
+
+        let (path, cost) = grid
+            .find_voxel_path(IVec3::new(4, 1, 4), IVec3::new(5, 1, 5), &agent)
+            .unwrap();
+        assert_eq!(path, vec![IVec3::new(4, 1, 4), IVec3::new(5, 1, 5)]);
+        assert_eq!(cost, DIAGONAL_COST);
+
+        let (path, cost) = grid
+            .find_voxel_path(IVec3::new(4, 1, 4), IVec3::new(10, 1, 10), &agent)
+            .unwrap();
+        assert_eq!(path.len(), 7);
+        assert_eq!(cost, 6 * DIAGONAL_COST);
+    }
+
+    #[test]
+    fn portal_path() {
+        let mut grid = floor_grid();
+        wall(&mut grid, SIZE - 4);
+        let agent = NavigationAgent::default();
+        let (start, goal) = (IVec3::new(8, 1, 2), IVec3::new(24, 1, 2));
+
+        let (_, around) = grid.find_voxel_path(start, goal, &agent).unwrap();
+
+        // a portal facing along x at x = 12 that comes out at x = 20
+        grid.portals.push(ExtractedPortal {
+            transformation: Mat4::from_translation(Vec3::X * 8.0 / VOXELS_PER_METER),
+            position: grid.voxel_to_world(IVec3::new(12, 1, 2)),
+            normal: Vec3::X,
+        });
+        let (path, cost) = grid.find_voxel_path(start, goal, &agent).unwrap();
+
+        // walk to x = 11, step through to x = 21 and walk on to the goal
+        assert!(cost < around);
+        assert_eq!(cost, 7 * STRAIGHT_COST);
+        assert!(path.contains(&IVec3::new(11, 1, 2)));
+        assert!(path.contains(&IVec3::new(21, 1, 2)));
+    }
+}
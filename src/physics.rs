@@ -1,15 +1,17 @@
 use crate::{
     voxel_pipeline::{
-        compute::{AnimationData, PhysicsData},
-        voxel_world::{ExtractedPortal, VoxelUniforms},
+        compute::{AnimationData, EmitterData, PhysicsData},
+        voxel_world::{ExtractedPortal, VoxelPortals, VoxelUniforms, MAX_PORTALS},
+        voxelization::{PortalIndex, VoxelizationMaterial},
     },
-    Box, BoxCollider, Edges, Particle, Portal, RenderGraphSettings, VoxelPhysics,
-    VoxelizationMaterial, VoxelizationMaterialType,
+    Box, BoxCollider, Edges, Particle, ParticleEmitter, Portal, RenderGraphSettings,
+    VoxelCharacterController, VoxelPhysics,
 };
 use bevy::{
     prelude::*,
     render::render_resource::MapMode,
     render::renderer::{RenderDevice, RenderQueue},
+    transform::TransformSystem,
     utils::HashMap,
 };
 
@@ -21,13 +23,26 @@ impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(PreUpdate, insert_physics_data)
             .add_systems(PostUpdate, extract_physics_data)
-            .add_systems(PostUpdate, extract_animation_data);
+            .add_systems(PostUpdate, extract_animation_data)
+            .add_systems(
+                PostUpdate,
+                (allocate_portal_indices, apply_deferred, extract_portal_data)
+                    .chain()
+                    .after(TransformSystem::TransformPropagate),
+            )
+            .add_systems(PostUpdate, extract_emitter_data);
     }
 }
 
 pub fn extract_physics_data(
     particle_query: Query<(&Transform, &VoxelPhysics, Entity), Without<BoxCollider>>,
-    box_query: Query<(&Transform, &VoxelPhysics, &BoxCollider, Entity)>,
+    box_query: Query<(
+        &Transform,
+        &VoxelPhysics,
+        &BoxCollider,
+        Option<&VoxelCharacterController>,
+        Entity,
+    )>,
     mut physics_data: ResMut<PhysicsData>,
     render_queue: Res<RenderQueue>,
 ) {
@@ -49,9 +64,16 @@ pub fn extract_physics_data(
     }
 
     // Add boxes
-    for (transform, voxel_physics, box_collider, entity) in box_query.iter() {
+    for (transform, voxel_physics, box_collider, controller, entity) in box_query.iter() {
         entities.insert(entity, type_buffer.header.len());
 
+        // only characters step up onto ledges
+        let step_height = controller.map_or(0, |controller| controller.step_height);
+        // voxels the collider grows upwards when a crouched character stands up
+        let headroom = controller.map_or(0, |controller| {
+            2 * (controller.half_size.y - box_collider.half_size.y).max(0) as u32
+        });
+
         type_buffer.push_object(1, |type_buffer| {
             type_buffer.push_vec3(transform.translation);
             type_buffer.push_vec3(voxel_physics.velocity);
@@ -60,6 +82,8 @@ pub fn extract_physics_data(
             type_buffer.push_vec3(Vec3::ZERO); // space to recieve hit data
             type_buffer.push_mat3(Mat3::IDENTITY); // space to recieve portal rotation
             type_buffer.push_ivec3(box_collider.half_size);
+            type_buffer.push_u32(step_height);
+            type_buffer.push_u32(headroom); // space to recieve whether there is room to stand
         });
     }
 
@@ -77,7 +101,12 @@ pub fn extract_physics_data(
 }
 
 pub fn insert_physics_data(
-    mut voxel_physics_query: Query<(&mut Transform, &mut VoxelPhysics, Entity)>,
+    mut voxel_physics_query: Query<(
+        &mut Transform,
+        &mut VoxelPhysics,
+        Option<&mut VoxelCharacterController>,
+        Entity,
+    )>,
     physics_data: Res<PhysicsData>,
     render_device: Res<RenderDevice>,
     render_graph_settings: Res<RenderGraphSettings>,
@@ -107,7 +136,8 @@ pub fn insert_physics_data(
         }
 
         // Process points and boxes
-        for (mut transform, mut voxel_physics, entity) in voxel_physics_query.iter_mut() {
+        for (mut transform, mut voxel_physics, controller, entity) in voxel_physics_query.iter_mut()
+        {
             if let Some(index) = physics_data.entities.get(&entity) {
                 let data_index = result[index + 1] as usize & 0xFFFFFF;
                 transform.translation = Vec3::new(
@@ -142,6 +172,9 @@ pub fn insert_physics_data(
                         bytemuck::cast(result[data_index + 23]),
                     ),
                 );
+                if let Some(mut controller) = controller {
+                    controller.can_stand = result[data_index + 28] != 0;
+                }
             }
         }
     }
@@ -201,6 +234,10 @@ impl TypeBuffer {
         self.data.push(bytemuck::cast(value));
     }
 
+    fn push_f32(&mut self, value: f32) {
+        self.data.push(bytemuck::cast(value));
+    }
+
     fn push_vec3(&mut self, value: Vec3) {
         self.data.push(bytemuck::cast(value.x));
         self.data.push(bytemuck::cast(value.y));
@@ -226,13 +263,89 @@ impl TypeBuffer {
     }
 }
 
+type NewPortalFilter = (
+    With<Portal>,
+    With<VoxelizationMaterial>,
+    Without<PortalIndex>,
+);
+type RemovedPortalFilter = (
+    With<PortalIndex>,
+    Or<(Without<Portal>, Without<VoxelizationMaterial>)>,
+);
+
+/// Gives new portals the lowest free index. It stays with the portal until the
+/// portal is removed, so other portals coming and going don't move it. Only
+/// voxelized portals get one, anything else has no voxels to write it to.
+fn allocate_portal_indices(
+    mut commands: Commands,
+    new_portals: Query<Entity, NewPortalFilter>,
+    removed_portals: Query<Entity, RemovedPortalFilter>,
+    portal_indices: Query<&PortalIndex, (With<Portal>, With<VoxelizationMaterial>)>,
+    mut warned: Local<bool>,
+) {
+    for entity in removed_portals.iter() {
+        commands.entity(entity).remove::<PortalIndex>();
+    }
+
+    // the index takes the place of the material, 0 is an empty voxel and 255
+    // samples the texture of the mesh
+    let mut used = [false; MAX_PORTALS];
+    used[0] = true;
+    used[MAX_PORTALS - 1] = true;
+    for portal_index in portal_indices.iter() {
+        used[portal_index.0 as usize] = true;
+    }
+
+    // portals left without an index are tried again every frame, but only
+    // warned about once until they all fit
+    let mut free = (0..MAX_PORTALS).filter(|i| !used[*i]);
+    for entity in new_portals.iter() {
+        let Some(index) = free.next() else {
+            if !*warned {
+                warn!(
+                    "Too many portals, only {} fit in the world",
+                    MAX_PORTALS - 2
+                );
+                *warned = true;
+            }
+            return;
+        };
+        commands.entity(entity).insert(PortalIndex(index as u8));
+    }
+    *warned = false;
+}
+
+/// Fills the portal table, each enabled portal sends what goes into it out of
+/// its target and the others are marked as disabled
+fn extract_portal_data(
+    portal_query: Query<(&GlobalTransform, &Portal, &PortalIndex)>,
+    target_query: Query<&GlobalTransform>,
+    mut voxel_portals: ResMut<VoxelPortals>,
+) {
+    *voxel_portals = VoxelPortals::default();
+    for (transform, portal, portal_index) in portal_query.iter() {
+        let target = match portal.enabled {
+            true => target_query.get(portal.target).ok(),
+            false => None,
+        };
+
+        voxel_portals.portals[portal_index.0 as usize] = match target {
+            Some(target) => ExtractedPortal {
+                transformation: target.compute_matrix() * transform.compute_matrix().inverse(),
+                position: transform.translation(),
+                normal: transform.forward(),
+            },
+            None => ExtractedPortal::DISABLED,
+        };
+    }
+}
+
 pub fn extract_animation_data(
     mut animation_data: ResMut<AnimationData>,
     particle_query: Query<(&Transform, &Particle)>,
-    mut portal_query: Query<(&Transform, &Portal, &mut VoxelizationMaterial)>,
     edges_query: Query<(&Transform, &Edges)>,
     boxes_query: Query<(&Transform, &Box)>,
-    mut voxel_uniforms: ResMut<VoxelUniforms>,
+    voxel_uniforms: Res<VoxelUniforms>,
     render_queue: Res<RenderQueue>,
 ) {
     let mut type_buffer = TypeBuffer::new();
@@ -271,40 +384,6 @@ pub fn extract_animation_data(
         });
     }
 
-    // Grab all the portails in pairs
-    voxel_uniforms.portals = [ExtractedPortal::default(); 32];
-
-    let mut portals: Vec<(&Transform, &Portal, Mut<VoxelizationMaterial>)> =
-        portal_query.iter_mut().collect();
-
-    for i in 0..portals.len() {
-        portals[i].2.material = VoxelizationMaterialType::Material(i as u8);
-        if i % 2 == 1 {
-            let first = &portals[i - 1];
-            let second = &portals[i];
-
-            let first_matrix = first.0.compute_matrix();
-            let second_matrix = second.0.compute_matrix();
-
-            let first_normal = -first.0.local_z();
-            let first_pos = first.0.translation;
-
-            let second_normal = -second.0.local_z();
-            let second_pos = second.0.translation;
-
-            voxel_uniforms.portals[i - 1] = ExtractedPortal {
-                transformation: second_matrix * first_matrix.inverse(),
-                position: first_pos,
-                normal: first_normal,
-            };
-            voxel_uniforms.portals[i] = ExtractedPortal {
-                transformation: first_matrix * second_matrix.inverse(),
-                position: second_pos,
-                normal: second_normal,
-            };
-        }
-    }
-
     animation_data.dispatch_size = type_buffer.header.len() as u32;
 
     // Copy animation data to the buffer
@@ -314,3 +393,46 @@ pub fn extract_animation_data(
         bytemuck::cast_slice(&type_buffer.finish()),
     );
 }
+
+pub fn extract_emitter_data(
+    emitter_query: Query<(&GlobalTransform, &ParticleEmitter)>,
+    mut emitter_data: ResMut<EmitterData>,
+    time: Res<Time>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mut type_buffer = TypeBuffer::new();
+
+    let elapsed = time.elapsed_seconds();
+    let last_elapsed = elapsed - time.delta_seconds();
+
+    // Only the number of particles to spawn is sent, the gpu does the rest
+    for (transform, emitter) in emitter_query.iter() {
+        let count =
+            (emitter.spawn_rate * elapsed).floor() - (emitter.spawn_rate * last_elapsed).floor();
+        if count <= 0.0 {
+            continue;
+        }
+
+        type_buffer.push_object(0, |type_buffer| {
+            type_buffer.push_vec3(transform.translation());
+            type_buffer.push_vec3(transform.forward());
+            type_buffer.push_vec3(emitter.gravity);
+            type_buffer.push_vec3(emitter.collision_effect.to_vec3());
+            type_buffer.push_u32(count as u32);
+            type_buffer.push_f32(emitter.cone_angle);
+            type_buffer.push_f32(emitter.speed);
+            type_buffer.push_f32(emitter.lifetime);
+            type_buffer.push_u32(emitter.material as u32 | ((emitter.flags as u32) << 8));
+            type_buffer.push_u32(emitter.settle as u32);
+        });
+    }
+
+    emitter_data.dispatch_size = type_buffer.header.len() as u32;
+
+    // Copy emitter data to the buffer
+    render_queue.write_buffer(
+        &emitter_data.emitter_buffer,
+        0,
+        bytemuck::cast_slice(&type_buffer.finish()),
+    );
+}
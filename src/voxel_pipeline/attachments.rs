@@ -1,5 +1,6 @@
 use crate::TraceSettings;
 use bevy::{
+    core::FrameCount,
     prelude::*,
     render::{
         extract_component::{ExtractComponent, ExtractComponentPlugin},
@@ -7,6 +8,7 @@ use bevy::{
         render_graph::{self, NodeRunError, RenderGraphContext, SlotInfo, SlotType, SlotValue},
         render_resource::*,
         renderer::RenderContext,
+        texture::FallbackImage,
     },
 };
 
@@ -23,8 +25,16 @@ impl Plugin for AttachmentsPlugin {
 #[derive(Component, Clone, ExtractComponent)]
 pub struct RenderAttachments {
     current_size: UVec2,
-    pub normal: Handle<Image>,
-    pub position: Handle<Image>,
+    current_render_size: UVec2,
+    /// the normals and positions are written on alternating frames, the other
+    /// one still holds the surfaces of the last frame
+    pub normal: [Handle<Image>; 2],
+    /// the position of the hit, alpha is its depth
+    pub position: [Handle<Image>; 2],
+    /// the blended frames of the temporal accumulation, alpha is how many
+    pub accumulation: Handle<Image>,
+    /// the last upscaled frame at full resolution
+    pub history: Handle<Image>,
 }
 
 fn add_render_attachments(
@@ -59,37 +69,60 @@ fn add_render_attachments(
 
         commands.entity(entity).insert(RenderAttachments {
             current_size: UVec2::new(1, 1),
-            normal: images.add(image.clone()),
-            position: images.add(highp_image),
+            current_render_size: UVec2::new(1, 1),
+            normal: [images.add(image.clone()), images.add(image.clone())],
+            position: [images.add(highp_image.clone()), images.add(highp_image)],
+            accumulation: images.add(image.clone()),
+            history: images.add(image),
         });
     }
 }
 
 fn resize_attachments(
     mut images: ResMut<Assets<Image>>,
-    mut query: Query<(&mut RenderAttachments, &Camera)>,
+    mut query: Query<(&mut RenderAttachments, &Camera, &TraceSettings)>,
 ) {
-    for (i, (mut render_attachments, camera)) in query.iter_mut().enumerate() {
-        let size = camera.physical_viewport_size().unwrap();
+    for (i, (mut render_attachments, camera, trace_settings)) in query.iter_mut().enumerate() {
+        // image targets can take a frame to be created
+        let Some(size) = camera.physical_viewport_size() else {
+            continue;
+        };
+        let render_size = trace_settings.render_size(size);
 
-        if size != render_attachments.current_size {
+        if size != render_attachments.current_size
+            || render_size != render_attachments.current_render_size
+        {
             render_attachments.current_size = size;
+            render_attachments.current_render_size = render_size;
             debug!(
                 "Resizing camera {}s attachments to ({}, {})",
-                i, size.x, size.y
+                i, render_size.x, render_size.y
             );
 
-            let size = Extent3d {
+            let history_image = images.get_mut(&render_attachments.history).unwrap();
+            history_image.resize(Extent3d {
                 width: size.x,
                 height: size.y,
                 depth_or_array_layers: 1,
+            });
+
+            // everything else is at the resolution the world is traced at
+            let size = Extent3d {
+                width: render_size.x,
+                height: render_size.y,
+                depth_or_array_layers: 1,
             };
 
-            let normal_image = images.get_mut(&render_attachments.normal).unwrap();
-            normal_image.resize(size);
+            for normal in &render_attachments.normal {
+                images.get_mut(normal).unwrap().resize(size);
+            }
 
-            let position_image = images.get_mut(&render_attachments.position).unwrap();
-            position_image.resize(size);
+            for position in &render_attachments.position {
+                images.get_mut(position).unwrap().resize(size);
+            }
+
+            let accumulation_image = images.get_mut(&render_attachments.accumulation).unwrap();
+            accumulation_image.resize(size);
         }
     }
 }
@@ -111,6 +144,10 @@ impl render_graph::Node for AttachmentsNode {
         vec![
             SlotInfo::new("normal", SlotType::TextureView),
             SlotInfo::new("position", SlotType::TextureView),
+            SlotInfo::new("last_normal", SlotType::TextureView),
+            SlotInfo::new("last_position", SlotType::TextureView),
+            SlotInfo::new("accumulation", SlotType::TextureView),
+            SlotInfo::new("history", SlotType::TextureView),
         ]
     }
 
@@ -126,17 +163,42 @@ impl render_graph::Node for AttachmentsNode {
     ) -> Result<(), NodeRunError> {
         let view_entity = graph.view_entity();
         let gpu_images = world.resource::<RenderAssets<Image>>();
-
-        let render_attachments = match self.query.get_manual(world, view_entity) {
-            Ok(result) => result,
-            Err(_) => panic!("Voxel camera missing component!"),
-        };
-
-        let normal = gpu_images.get(&render_attachments.normal).unwrap();
-        let position = gpu_images.get(&render_attachments.position).unwrap();
+        let current = world.resource::<FrameCount>().0 as usize % 2;
+        let last = 1 - current;
+
+        let (normal, position, last_normal, last_position, accumulation, history) =
+            match self.query.get_manual(world, view_entity) {
+                Ok(render_attachments) => (
+                    gpu_images.get(&render_attachments.normal[current]).unwrap(),
+                    gpu_images
+                        .get(&render_attachments.position[current])
+                        .unwrap(),
+                    gpu_images.get(&render_attachments.normal[last]).unwrap(),
+                    gpu_images.get(&render_attachments.position[last]).unwrap(),
+                    gpu_images.get(&render_attachments.accumulation).unwrap(),
+                    gpu_images.get(&render_attachments.history).unwrap(),
+                ),
+                // cameras in the core 3d graph that aren't traced still need every
+                // slot filled, the trace skips them anyway
+                Err(_) => {
+                    let fallback_image = &world.resource::<FallbackImage>().d2;
+                    (
+                        fallback_image,
+                        fallback_image,
+                        fallback_image,
+                        fallback_image,
+                        fallback_image,
+                        fallback_image,
+                    )
+                }
+            };
 
         let normal = normal.texture_view.clone();
         let position = position.texture_view.clone();
+        let last_normal = last_normal.texture_view.clone();
+        let last_position = last_position.texture_view.clone();
+        let accumulation = accumulation.texture_view.clone();
+        let history = history.texture_view.clone();
 
         graph
             .set_output("normal", SlotValue::TextureView(normal))
@@ -144,6 +206,18 @@ impl render_graph::Node for AttachmentsNode {
         graph
             .set_output("position", SlotValue::TextureView(position))
             .unwrap();
+        graph
+            .set_output("last_normal", SlotValue::TextureView(last_normal))
+            .unwrap();
+        graph
+            .set_output("last_position", SlotValue::TextureView(last_position))
+            .unwrap();
+        graph
+            .set_output("accumulation", SlotValue::TextureView(accumulation))
+            .unwrap();
+        graph
+            .set_output("history", SlotValue::TextureView(history))
+            .unwrap();
 
         Ok(())
     }
@@ -0,0 +1,106 @@
+#define_import_path bevy_voxel_engine::collision
+
+#import bevy_voxel_engine::common::{
+    VOXELS_PER_METER,
+    Particle,
+    Particles,
+    Edits,
+    ANIMATION_FLAG,
+    hash,
+}
+#import bevy_voxel_engine::bindings::{
+    voxel_world,
+    voxel_uniforms,
+}
+
+struct ComputeUniforms {
+    time: f32,
+    delta_time: f32,
+    debris_lifetime: f32,
+    debris_speed: f32,
+    debris_gravity: vec3<f32>,
+    debris_settle: u32,
+    integrity_collapse: u32,
+}
+
+@group(1) @binding(0)
+var<uniform> compute_uniforms: ComputeUniforms;
+@group(1) @binding(3)
+var<storage, read_write> particles: Particles;
+@group(1) @binding(5)
+var<storage, read_write> edits: Edits;
+
+fn in_texture_bounds(pos: vec3<i32>) -> bool {
+    return all(pos >= vec3(0)) && all(pos < vec3(i32(voxel_uniforms.texture_size)));
+}
+
+fn spawn_particle(particle: Particle) {
+    let index = atomicAdd(&particles.next, 1u) % arrayLength(&particles.particles);
+    particles.particles[index] = particle;
+}
+
+// remember where voxels were destroyed so the integrity pass can check the area
+fn record_edit(texture_coords: vec3<i32>) {
+    let index = atomicAdd(&edits.count, 1u);
+    if (index < arrayLength(&edits.positions)) {
+        edits.positions[index] = texture_coords;
+    }
+}
+
+// Applies a CollisionEffect to the voxels in its radius around texture_coords.
+// Destroyed voxels are thrown away from hit_pos as debris particles.
+fn apply_collision_effect(texture_coords: vec3<i32>, hit_pos: vec3<f32>, hit_normal: vec3<f32>, collision_effect: vec3<f32>) {
+    if (collision_effect.x == 1.0) {
+        record_edit(texture_coords);
+    }
+
+    let radius = collision_effect.y;
+    var debris = 0u;
+    let range = i32(ceil(radius * VOXELS_PER_METER));
+    for (var x = -range; x <= range; x++) {
+        for (var y = -range; y <= range; y++) {
+            for (var z = -range; z <= range; z++) {
+                let offset = vec3(x, y, z);
+                let texture_coords = texture_coords + offset;
+                if (length(vec3<f32>(offset) / VOXELS_PER_METER) >= radius || !in_texture_bounds(texture_coords)) {
+                    continue;
+                }
+
+                // Destroy
+                if (collision_effect.x == 1.0) {
+                    let voxel = textureLoad(voxel_world, texture_coords.zyx).r;
+                    let max_debris = bitcast<u32>(collision_effect.z);
+                    if (debris < max_debris && (voxel & 0xFFu) != 0u && ((voxel >> 8u) & ANIMATION_FLAG) == 0u) {
+                        // throw the voxel away from the impact with a bit of noise
+                        let voxel_pos = (vec3<f32>(texture_coords) + 0.5 - vec3(f32(voxel_uniforms.texture_size) / 2.0)) / VOXELS_PER_METER;
+                        let rand = hash(vec3<u32>(texture_coords) + u32(compute_uniforms.time * 1000.0)) * 2.0 - 1.0;
+                        let direction = normalize(voxel_pos - hit_pos + hit_normal * 0.5 + rand * 0.5);
+                        spawn_particle(Particle(
+                            voxel_pos,
+                            compute_uniforms.debris_lifetime * (0.75 + 0.25 * rand.x),
+                            direction * compute_uniforms.debris_speed,
+                            voxel,
+                            compute_uniforms.debris_gravity,
+                            compute_uniforms.debris_settle,
+                            vec3(0.0),
+                        ));
+                        debris += 1u;
+                    }
+                    textureStore(voxel_world, texture_coords.zyx, vec4(0u));
+                }
+                // Place
+                if (collision_effect.x == 2.0) {
+                    let material = bitcast<u32>(collision_effect.z);
+                    textureStore(voxel_world, texture_coords.zyx, vec4(material));
+                }
+                // Set Flags
+                if (collision_effect.x == 3.0) {
+                    let flags = bitcast<u32>(collision_effect.z);
+                    var voxel = textureLoad(voxel_world, texture_coords.zyx).r;
+                    voxel |= flags << 8u;
+                    textureStore(voxel_world, texture_coords.zyx, vec4(voxel));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use super::{ComputeData, EDIT_DISPATCH_SIZE, MAX_EDITS};
+use crate::{voxel_pipeline::voxel_world::VoxelData, RenderGraphSettings};
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::RenderContext,
+    },
+};
+use std::borrow::Cow;
+
+pub struct IntegrityNode;
+
+#[derive(Resource)]
+pub struct Pipeline {
+    dispatch_pipeline: CachedComputePipelineId,
+    integrity_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let compute_bind_group_layout = world.resource::<ComputeData>().bind_group_layout.clone();
+
+        let asset_server = world.resource::<AssetServer>();
+        let shader =
+            asset_server.load("embedded://bevy_voxel_engine/voxel_pipeline/compute/integrity.wgsl");
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+        let dispatch_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("integrity dispatch pipeline")),
+            layout: vec![
+                voxel_bind_group_layout.clone(),
+                compute_bind_group_layout.clone(),
+            ],
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from("prepare_dispatches"),
+            push_constant_ranges: vec![],
+        });
+        let integrity_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("integrity pipeline")),
+            layout: vec![voxel_bind_group_layout, compute_bind_group_layout],
+            shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("integrity"),
+            push_constant_ranges: vec![],
+        });
+
+        Pipeline {
+            dispatch_pipeline,
+            integrity_pipeline,
+        }
+    }
+}
+
+impl render_graph::Node for IntegrityNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let voxel_data = world.resource::<VoxelData>();
+        let compute_data = world.resource::<ComputeData>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_graph_settings = world.resource::<RenderGraphSettings>();
+        let pipeline = world.resource::<Pipeline>();
+
+        if let (true, Some(dispatch_pipeline), Some(integrity_pipeline)) = (
+            render_graph_settings.integrity,
+            pipeline_cache.get_compute_pipeline(pipeline.dispatch_pipeline),
+            pipeline_cache.get_compute_pipeline(pipeline.integrity_pipeline),
+        ) {
+            let mut pass = render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor::default());
+
+            pass.set_bind_group(0, &voxel_data.bind_group, &[]);
+            pass.set_bind_group(1, &compute_data.bind_group, &[]);
+
+            // only as many edit slots as there are edits get a workgroup
+            pass.set_pipeline(dispatch_pipeline);
+            pass.dispatch_workgroups(MAX_EDITS.div_ceil(64), 1, 1);
+
+            // one dispatch per edit so that edits with overlapping regions run
+            // one after another and see each other's changes
+            pass.set_pipeline(integrity_pipeline);
+            for i in 0..MAX_EDITS as u64 {
+                pass.dispatch_workgroups_indirect(
+                    &compute_data.edit_dispatch_buffer,
+                    i * EDIT_DISPATCH_SIZE,
+                );
+            }
+        }
+
+        // the edits have been handled (or ignored), start collecting the next ones
+        render_context
+            .command_encoder()
+            .clear_buffer(&compute_data.edit_buffer, 0, None);
+
+        Ok(())
+    }
+}
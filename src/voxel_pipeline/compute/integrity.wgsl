@@ -0,0 +1,199 @@
+#import bevy_voxel_engine::common::{
+    VOXELS_PER_METER,
+    Particle,
+    ANIMATION_FLAG,
+    SAND_FLAG,
+    ANCHOR_FLAG,
+}
+#import bevy_voxel_engine::bindings::{
+    voxel_world,
+    voxel_uniforms,
+}
+#import bevy_voxel_engine::collision::{
+    compute_uniforms,
+    edits,
+    in_texture_bounds,
+    spawn_particle,
+}
+
+// The area around every edit that is checked. Anything that reaches the edge of
+// the region is assumed to be connected to the rest of the world. Every column
+// along y is stored as two u32 bit masks.
+const REGION_SIZE = vec3(32, 64, 32);
+const REGION_OFFSET = vec3(16, 16, 16);
+const COLUMNS = 1024u;
+const COLUMNS_PER_THREAD = 4u;
+const MAX_ITERATIONS = 128;
+
+// indirect dispatch arguments for every edit slot
+@group(1) @binding(8)
+var<storage, read_write> edit_dispatches: array<vec4<u32>>;
+
+var<workgroup> supported: array<atomic<u32>, 2048>;
+var<workgroup> changed: atomic<u32>;
+var<workgroup> edit_index: u32;
+var<workgroup> edit_active: u32;
+var<workgroup> done: u32;
+
+fn column_pos(column: u32) -> vec2<i32> {
+    return vec2(i32(column / 32u), i32(column % 32u));
+}
+
+fn load_supported(column: vec2<i32>) -> vec2<u32> {
+    // outside of the region is assumed to hold everything up
+    if (any(column < vec2(0)) || any(column >= REGION_SIZE.xz)) {
+        return vec2(0xFFFFFFFFu);
+    }
+    let index = u32(column.x * 32 + column.y) * 2u;
+    return vec2(atomicLoad(&supported[index]), atomicLoad(&supported[index + 1u]));
+}
+
+fn store_supported(column: vec2<i32>, mask: vec2<u32>) {
+    let index = u32(column.x * 32 + column.y) * 2u;
+    atomicOr(&supported[index], mask.x);
+    atomicOr(&supported[index + 1u], mask.y);
+}
+
+fn has_bit(mask: vec2<u32>, y: i32) -> bool {
+    return ((mask[y / 32] >> u32(y % 32)) & 1u) != 0u;
+}
+
+fn set_bit(mask: vec2<u32>, y: i32) -> vec2<u32> {
+    var new_mask = mask;
+    new_mask[y / 32] |= 1u << u32(y % 32);
+    return new_mask;
+}
+
+// grow the seeds along the solid runs of a column
+fn fill_column(seeds: vec2<u32>, solid: vec2<u32>) -> vec2<u32> {
+    var mask = seeds & solid;
+    for (var i = 0; i < REGION_SIZE.y; i++) {
+        let up = vec2(mask.x << 1u, (mask.y << 1u) | (mask.x >> 31u));
+        let down = vec2((mask.x >> 1u) | (mask.y << 31u), mask.y >> 1u);
+        let next = (mask | up | down) & solid;
+        if (all(next == mask)) {
+            break;
+        }
+        mask = next;
+    }
+    return mask;
+}
+
+// slots past the edit count get an empty dispatch
+@compute @workgroup_size(64, 1, 1)
+fn prepare_dispatches(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let slot = invocation_id.x;
+    if (slot >= arrayLength(&edit_dispatches)) {
+        return;
+    }
+    let count = min(atomicLoad(&edits.count), arrayLength(&edits.positions));
+    edit_dispatches[slot] = vec4(u32(slot < count), 1u, 1u, 0u);
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn integrity(@builtin(local_invocation_index) local_index: u32) {
+    // every dispatch takes the next edit, so overlapping regions are never
+    // written by two workgroups at the same time
+    if (local_index == 0u) {
+        let count = min(atomicLoad(&edits.count), arrayLength(&edits.positions));
+        edit_index = atomicAdd(&edits.cursor, 1u);
+        edit_active = u32(edit_index < count);
+        atomicStore(&changed, 0u);
+    }
+    for (var i = local_index; i < COLUMNS * 2u; i += 256u) {
+        atomicStore(&supported[i], 0u);
+    }
+    if (workgroupUniformLoad(&edit_active) == 0u) {
+        return;
+    }
+
+    let texture_size = i32(voxel_uniforms.texture_size);
+    let edit = edits.positions[workgroupUniformLoad(&edit_index)];
+    let origin = clamp(edit - REGION_OFFSET, vec3(0), max(vec3(texture_size) - REGION_SIZE, vec3(0)));
+
+    // find the solid voxels and seed the top, bottom and anchor voxels
+    var solid: array<vec2<u32>, 4>;
+    for (var c = 0u; c < COLUMNS_PER_THREAD; c++) {
+        let column = column_pos(local_index + c * 256u);
+        var seeds = vec2(1u, 1u << 31u);
+        solid[c] = vec2(0u);
+        for (var y = 0; y < REGION_SIZE.y; y++) {
+            let pos = origin + vec3(column.x, y, column.y);
+            if (!in_texture_bounds(pos)) {
+                continue;
+            }
+            let voxel = textureLoad(voxel_world, pos.zyx).r;
+            let flags = voxel >> 8u;
+            if ((voxel & 0xFFu) != 0u && (flags & ANIMATION_FLAG) == 0u) {
+                solid[c] = set_bit(solid[c], y);
+                if ((flags & ANCHOR_FLAG) != 0u) {
+                    seeds = set_bit(seeds, y);
+                }
+            }
+        }
+        store_supported(column, fill_column(seeds, solid[c]));
+    }
+    workgroupBarrier();
+
+    // spread the support sideways until nothing changes
+    for (var i = 0; i < MAX_ITERATIONS; i++) {
+        for (var c = 0u; c < COLUMNS_PER_THREAD; c++) {
+            let column = column_pos(local_index + c * 256u);
+            let current = load_supported(column);
+            let neighbors = load_supported(column + vec2(1, 0))
+                | load_supported(column + vec2(-1, 0))
+                | load_supported(column + vec2(0, 1))
+                | load_supported(column + vec2(0, -1));
+            let next = fill_column(current | neighbors, solid[c]);
+            if (any(next != current)) {
+                store_supported(column, next);
+                atomicStore(&changed, 1u);
+            }
+        }
+        workgroupBarrier();
+        if (local_index == 0u) {
+            done = u32(atomicExchange(&changed, 0u) == 0u);
+        }
+        if (workgroupUniformLoad(&done) != 0u) {
+            break;
+        }
+    }
+
+    // collapse everything that is left floating
+    for (var c = 0u; c < COLUMNS_PER_THREAD; c++) {
+        let column = column_pos(local_index + c * 256u);
+        let floating = solid[c] & ~load_supported(column);
+        if (all(floating == vec2(0u))) {
+            continue;
+        }
+        for (var y = 0; y < REGION_SIZE.y; y++) {
+            if (!has_bit(floating, y)) {
+                continue;
+            }
+            let pos = origin + vec3(column.x, y, column.y);
+            let voxel = textureLoad(voxel_world, pos.zyx).r;
+            if ((voxel & 0xFFu) == 0u) {
+                continue;
+            }
+
+            // Sand
+            if (compute_uniforms.integrity_collapse == 0u) {
+                textureStore(voxel_world, pos.zyx, vec4(voxel | (SAND_FLAG << 8u)));
+            }
+            // Debris
+            if (compute_uniforms.integrity_collapse == 1u) {
+                let voxel_pos = (vec3<f32>(pos) + 0.5 - vec3(f32(texture_size) / 2.0)) / VOXELS_PER_METER;
+                spawn_particle(Particle(
+                    voxel_pos,
+                    compute_uniforms.debris_lifetime,
+                    vec3(0.0),
+                    voxel,
+                    compute_uniforms.debris_gravity,
+                    compute_uniforms.debris_settle,
+                    vec3(0.0),
+                ));
+                textureStore(voxel_world, pos.zyx, vec4(0u));
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{voxel_pipeline::voxel_world::VoxelData, RenderGraphSettings};
+use bevy::{
+    core::FrameCount,
+    prelude::*,
+    render::{
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+    },
+};
+use std::borrow::Cow;
+
+/// Cells along each side of the light volume, it always covers the whole world
+pub const LIGHT_VOLUME_SIZE: u32 = 64;
+const WORKGROUP_SIZE: u32 = 4;
+
+pub struct LightVolumeNode;
+
+/// Low resolution light coming off of emissive voxels, it is spread out a step
+/// every frame by swapping between the two textures
+#[derive(Resource)]
+pub struct LightVolume {
+    views: [TextureView; 2],
+    pub sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+}
+
+impl LightVolume {
+    /// The texture written to this frame
+    pub fn view(&self, frame_count: &FrameCount) -> &TextureView {
+        &self.views[frame_count.0 as usize % 2]
+    }
+}
+
+impl FromWorld for LightVolume {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let views = [0, 1].map(|_| {
+            render_device
+                .create_texture(&TextureDescriptor {
+                    label: Some("light volume"),
+                    size: Extent3d {
+                        width: LIGHT_VOLUME_SIZE,
+                        height: LIGHT_VOLUME_SIZE,
+                        depth_or_array_layers: LIGHT_VOLUME_SIZE,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D3,
+                    format: TextureFormat::Rgba16Float,
+                    usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&TextureViewDescriptor::default())
+        });
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("light volume sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        let bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("light volume bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::Rgba16Float,
+                            view_dimension: TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        // read from one texture and write to the other
+        let bind_groups = [(1, 0), (0, 1)].map(|(last, next)| {
+            render_device.create_bind_group(
+                Some("light volume bind group"),
+                &bind_group_layout,
+                &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&views[last]),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&views[next]),
+                    },
+                ],
+            )
+        });
+
+        LightVolume {
+            views,
+            sampler,
+            bind_group_layout,
+            bind_groups,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Pipeline(CachedComputePipelineId);
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let light_volume_bind_group_layout =
+            world.resource::<LightVolume>().bind_group_layout.clone();
+
+        let asset_server = world.resource::<AssetServer>();
+        let shader = asset_server
+            .load("embedded://bevy_voxel_engine/voxel_pipeline/compute/light_volume.wgsl");
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+        let light_volume_pipeline =
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("light volume pipeline")),
+                layout: vec![voxel_bind_group_layout, light_volume_bind_group_layout],
+                shader,
+                shader_defs: vec![],
+                entry_point: Cow::from("light_volume"),
+                push_constant_ranges: vec![],
+            });
+
+        Pipeline(light_volume_pipeline)
+    }
+}
+
+impl render_graph::Node for LightVolumeNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let voxel_data = world.resource::<VoxelData>();
+        let light_volume = world.resource::<LightVolume>();
+        let frame_count = world.resource::<FrameCount>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_graph_settings = world.resource::<RenderGraphSettings>();
+
+        if !render_graph_settings.light_volume {
+            return Ok(());
+        }
+
+        let pipeline = match pipeline_cache.get_compute_pipeline(world.resource::<Pipeline>().0) {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        pass.set_bind_group(0, &voxel_data.bind_group, &[]);
+        pass.set_bind_group(
+            1,
+            &light_volume.bind_groups[frame_count.0 as usize % 2],
+            &[],
+        );
+
+        let dispatch_size = LIGHT_VOLUME_SIZE / WORKGROUP_SIZE;
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups(dispatch_size, dispatch_size, dispatch_size);
+
+        Ok(())
+    }
+}
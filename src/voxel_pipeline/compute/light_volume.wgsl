@@ -0,0 +1,76 @@
+#import bevy_voxel_engine::common::{
+    VoxelUniforms,
+    ANIMATION_FLAG,
+    PORTAL_FLAG,
+}
+#import bevy_voxel_engine::bindings::{
+    voxel_world,
+    voxel_uniforms,
+    gh
+}
+
+@group(1) @binding(0)
+var last_light: texture_3d<f32>;
+@group(1) @binding(1)
+var next_light: texture_storage_3d<rgba16float, write>;
+
+const LIGHT_VOLUME_SIZE = 64;
+// how much of the light is left after moving one cell
+const PROPAGATION = 0.8;
+
+// Every cell takes the light of its emissive voxels or the brightest of its
+// neighbours, whichever is brighter. Light spreads one cell per frame and
+// fades out again when the voxels giving off the light are gone.
+@compute @workgroup_size(4, 4, 4)
+fn light_volume(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let cell = vec3<i32>(invocation_id);
+    let texture_size = i32(voxel_uniforms.texture_size);
+    let voxels_per_cell = max(texture_size / LIGHT_VOLUME_SIZE, 1);
+
+    var emission = vec3(0.0);
+    var solid = 0;
+    for (var z = 0; z < voxels_per_cell; z++) {
+        for (var y = 0; y < voxels_per_cell; y++) {
+            for (var x = 0; x < voxels_per_cell; x++) {
+                let pos = cell * voxels_per_cell + vec3(x, y, z);
+                if (any(pos >= vec3(texture_size))) {
+                    continue;
+                }
+
+                let voxel = textureLoad(voxel_world, pos.zyx).r;
+                let flags = voxel >> 8u;
+                if ((voxel & 0xFFu) == 0u || (flags & (ANIMATION_FLAG | PORTAL_FLAG)) != 0u) {
+                    continue;
+                }
+
+                // light shines through transparent voxels
+                if (voxel_uniforms.materials[voxel & 0xFFu].transparency == 0.0) {
+                    solid += 1;
+                }
+                let colour = voxel_uniforms.pallete[voxel & 0xFFu].rgb;
+                emission = max(emission, colour * voxel_uniforms.materials[voxel & 0xFFu].emission);
+            }
+        }
+    }
+
+    // completely solid cells block the light
+    var light = emission;
+    if (solid < voxels_per_cell * voxels_per_cell * voxels_per_cell) {
+        var neighbors = array<vec3<i32>, 6>(
+            vec3(1, 0, 0),
+            vec3(-1, 0, 0),
+            vec3(0, 1, 0),
+            vec3(0, -1, 0),
+            vec3(0, 0, 1),
+            vec3(0, 0, -1),
+        );
+        for (var i = 0; i < 6; i++) {
+            let neighbor = cell + neighbors[i];
+            if (all(neighbor >= vec3(0)) && all(neighbor < vec3(LIGHT_VOLUME_SIZE))) {
+                light = max(light, textureLoad(last_light, neighbor, 0).rgb * PROPAGATION);
+            }
+        }
+    }
+
+    textureStore(next_light, cell, vec4(light, 1.0));
+}
@@ -10,8 +10,13 @@ use bevy::{
         renderer::{RenderContext, RenderDevice},
     },
 };
-use std::{borrow::Cow, num::NonZeroU32};
+use std::borrow::Cow;
 
+const WORKGROUP_SIZE: u32 = 4;
+
+/// Averages the world into `VoxelData::mip_texture` and picks the most common
+/// material of every cell for `VoxelData::mip_material_texture` every frame for
+/// the level of detail tracing
 pub struct MipNode;
 
 #[derive(Resource)]
@@ -26,9 +31,11 @@ impl FromWorld for Pipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
 
+        // the mips are written through storage views, so they can't be bound
+        // with the voxel bind group that samples the whole texture
         let copy_bind_group_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: None,
+                label: Some("mip copy bind group layout"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
@@ -44,7 +51,7 @@ impl FromWorld for Pipeline {
                         binding: 1,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadOnly,
+                            access: StorageTextureAccess::ReadWrite,
                             format: TextureFormat::R16Uint,
                             view_dimension: TextureViewDimension::D3,
                         },
@@ -54,25 +61,35 @@ impl FromWorld for Pipeline {
                         binding: 2,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
+                            access: StorageTextureAccess::WriteOnly,
                             format: TextureFormat::Rgba8Unorm,
                             view_dimension: TextureViewDimension::D3,
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::R16Uint,
+                            view_dimension: TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
                 ],
             });
         let mip_bind_group_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: None,
+                label: Some("mip bind group layout"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
                         visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
                             view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
                         },
                         count: None,
                     },
@@ -80,37 +97,52 @@ impl FromWorld for Pipeline {
                         binding: 1,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
+                            access: StorageTextureAccess::WriteOnly,
                             format: TextureFormat::Rgba8Unorm,
                             view_dimension: TextureViewDimension::D3,
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Uint,
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::R16Uint,
+                            view_dimension: TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        // let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
-        let pipeline_cache = world.resource_mut::<PipelineCache>();
-let asset_server = world.resource_mut::<AssetServer>();
+        let asset_server = world.resource::<AssetServer>();
+        let shader =
+            asset_server.load("embedded://bevy_voxel_engine/voxel_pipeline/compute/mip.wgsl");
 
+        let pipeline_cache = world.resource::<PipelineCache>();
         let copy_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: Some(Cow::from("copy pipeline")),
-            layout: vec![
-                copy_bind_group_layout.clone(),
-                mip_bind_group_layout.clone(),
-            ],
-            shader: super::MIP_SHADER_HANDLE.typed(),
+            label: Some(Cow::from("mip copy pipeline")),
+            layout: vec![copy_bind_group_layout.clone()],
+            shader: shader.clone(),
             shader_defs: vec![],
             entry_point: Cow::from("copy"),
             push_constant_ranges: vec![],
         });
         let mip_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
             label: Some(Cow::from("mip pipeline")),
-            layout: vec![
-                copy_bind_group_layout.clone(),
-                mip_bind_group_layout.clone(),
-            ],
-            shader: super::MIP_SHADER_HANDLE.typed(),
+            layout: vec![mip_bind_group_layout.clone()],
+            shader,
             shader_defs: vec![],
             entry_point: Cow::from("mip"),
             push_constant_ranges: vec![],
@@ -143,15 +175,31 @@ impl render_graph::Node for MipNode {
             return Ok(());
         }
 
-        // copy texture bind group
-        let texture_view = voxel_data.mip_texture.create_view(&TextureViewDescriptor {
-            mip_level_count: NonZeroU32::new(1),
-            ..default()
-        });
-        let copy_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &pipelines.copy_bind_group_layout,
-            entries: &[
+        let (copy_pipeline, mip_pipeline) = match (
+            pipeline_cache.get_compute_pipeline(pipelines.copy_pipeline),
+            pipeline_cache.get_compute_pipeline(pipelines.mip_pipeline),
+        ) {
+            (Some(copy_pipeline), Some(mip_pipeline)) => (copy_pipeline, mip_pipeline),
+            _ => return Ok(()),
+        };
+
+        let mip_view = |texture: &Texture, mip| {
+            texture.create_view(&TextureViewDescriptor {
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                ..default()
+            })
+        };
+        let dispatch_size =
+            |mip: u32| (voxel_uniforms.texture_size >> mip).div_ceil(WORKGROUP_SIZE);
+
+        // copy the colour and material of every voxel into the first mip
+        let first_mip = mip_view(&voxel_data.mip_texture, 0);
+        let first_material_mip = mip_view(&voxel_data.mip_material_texture, 0);
+        let copy_bind_group = render_device.create_bind_group(
+            Some("mip copy bind group"),
+            &pipelines.copy_bind_group_layout,
+            &[
                 BindGroupEntry {
                     binding: 0,
                     resource: voxel_data.uniform_buffer.binding().unwrap(),
@@ -162,67 +210,38 @@ impl render_graph::Node for MipNode {
                 },
                 BindGroupEntry {
                     binding: 2,
-                    resource: BindingResource::TextureView(&texture_view),
-                },
-            ],
-        });
-
-        let mip_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &pipelines.mip_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&texture_view),
+                    resource: BindingResource::TextureView(&first_mip),
                 },
                 BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(&texture_view),
+                    binding: 3,
+                    resource: BindingResource::TextureView(&first_material_mip),
                 },
             ],
-        });
-
-        // copy mip texture
-        let copy_pipeline = match pipeline_cache.get_compute_pipeline(pipelines.copy_pipeline) {
-            Some(pipeline) => pipeline,
-            None => return Ok(()),
-        };
+        );
 
         {
             let mut pass = render_context
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            let dispatch_size = voxel_uniforms.texture_size / 4;
             pass.set_bind_group(0, &copy_bind_group, &[]);
-            pass.set_bind_group(1, &mip_bind_group, &[]);
 
+            let size = dispatch_size(0);
             pass.set_pipeline(copy_pipeline);
-            pass.dispatch_workgroups(dispatch_size, dispatch_size, dispatch_size);
+            pass.dispatch_workgroups(size, size, size);
         }
 
-        // mip texture
-        let mip_pipeline = match pipeline_cache.get_compute_pipeline(pipelines.mip_pipeline) {
-            Some(pipeline) => pipeline,
-            None => return Ok(()),
-        };
-
-        for i in 1..voxel_uniforms.texture_size.trailing_zeros() {
-            let from_view = voxel_data.mip_texture.create_view(&TextureViewDescriptor {
-                base_mip_level: i - 1,
-                mip_level_count: NonZeroU32::new(1),
-                ..default()
-            });
-            let to_view = voxel_data.mip_texture.create_view(&TextureViewDescriptor {
-                base_mip_level: i,
-                mip_level_count: NonZeroU32::new(1),
-                ..default()
-            });
+        // then average each mip into the next one
+        for mip in 1..voxel_data.mip_texture.mip_level_count() {
+            let from_view = mip_view(&voxel_data.mip_texture, mip - 1);
+            let to_view = mip_view(&voxel_data.mip_texture, mip);
+            let from_material_view = mip_view(&voxel_data.mip_material_texture, mip - 1);
+            let to_material_view = mip_view(&voxel_data.mip_material_texture, mip);
 
-            let mip_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-                label: None,
-                layout: &pipelines.mip_bind_group_layout,
-                entries: &[
+            let mip_bind_group = render_device.create_bind_group(
+                Some("mip bind group"),
+                &pipelines.mip_bind_group_layout,
+                &[
                     BindGroupEntry {
                         binding: 0,
                         resource: BindingResource::TextureView(&from_view),
@@ -231,19 +250,26 @@ impl render_graph::Node for MipNode {
                         binding: 1,
                         resource: BindingResource::TextureView(&to_view),
                     },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&from_material_view),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(&to_material_view),
+                    },
                 ],
-            });
+            );
 
             let mut pass = render_context
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            let dispatch_size = voxel_uniforms.texture_size / 4 / (i + 1);
-            pass.set_bind_group(0, &copy_bind_group, &[]);
-            pass.set_bind_group(1, &mip_bind_group, &[]);
+            pass.set_bind_group(0, &mip_bind_group, &[]);
 
+            let size = dispatch_size(mip);
             pass.set_pipeline(mip_pipeline);
-            pass.dispatch_workgroups(dispatch_size, dispatch_size, dispatch_size);
+            pass.dispatch_workgroups(size, size, size);
         }
 
         Ok(())
@@ -1,17 +1,27 @@
-#import bevy_voxel_engine::common
+#import bevy_voxel_engine::common::{
+    VoxelUniforms,
+    PORTAL_FLAG
+}
 
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read>;
+var voxel_world: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
-var mip_texture: texture_storage_3d<rgba8unorm, read_write>; 
-
-@group(1) @binding(0)
-var from_texture: texture_storage_3d<rgba8unorm, read_write>; 
-@group(1) @binding(1)
-var to_texture: texture_storage_3d<rgba8unorm, read_write>;
+var mip_texture: texture_storage_3d<rgba8unorm, write>;
+@group(0) @binding(3)
+var mip_material: texture_storage_3d<r16uint, write>;
 
+// the mip pass has its own bind group, the mips it writes to can't be bound
+// anywhere else at the same time
+@group(0) @binding(0)
+var from_texture: texture_3d<f32>;
+@group(0) @binding(1)
+var to_texture: texture_storage_3d<rgba8unorm, write>;
+@group(0) @binding(2)
+var from_material: texture_3d<u32>;
+@group(0) @binding(3)
+var to_material: texture_storage_3d<r16uint, write>;
 
 fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
     let texture_value = textureLoad(voxel_world, pos.zyx).r;
@@ -21,36 +31,64 @@ fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
     );
 }
 
+// Fills the first mip with the colour and material of every voxel. Portals
+// are left out, rays that stop at a coarser mip see through them like empty
+// space.
 @compute @workgroup_size(4, 4, 4)
 fn copy(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let pos = vec3(i32(invocation_id.x), i32(invocation_id.y), i32(invocation_id.z));
+    let pos = vec3<i32>(invocation_id);
     let material = get_texture_value(pos);
-    if material.x != 0u {
-        textureStore(mip_texture, pos.zyx, vec4(voxel_uniforms.materials[material.x].rgb, 1.0));
+    if material.x != 0u && (material.y & PORTAL_FLAG) == 0u {
+        textureStore(mip_texture, pos.zyx, vec4(voxel_uniforms.pallete[material.x].rgb, 1.0));
+        textureStore(mip_material, pos.zyx, vec4(material.x | (material.y << 8u)));
     } else {
         textureStore(mip_texture, pos.zyx, vec4(0.0));
+        textureStore(mip_material, pos.zyx, vec4(0u));
     }
 }
 
+// Averages 2x2x2 cells of the last mip, the alpha is how much of the cell is
+// filled and the colour is the average of the filled voxels. The material is
+// the one most of the filled voxels have.
 @compute @workgroup_size(4, 4, 4)
 fn mip(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let pos = vec3(i32(invocation_id.x), i32(invocation_id.y), i32(invocation_id.z));
+    let pos = vec3<i32>(invocation_id);
+    if any(pos >= vec3<i32>(textureDimensions(to_texture))) {
+        return;
+    }
 
     var sum = vec3(0.0);
     var alpha = 0.0;
-    for (var i = 0u; i < 8u; i += 1u) {
+    var materials: array<u32, 8>;
+    for (var i = 0u; i < 8u; i++) {
         let offset = vec3(
             i & 1u,
             (i >> 1u) & 1u,
             (i >> 2u) & 1u,
         );
-        let value = textureLoad(from_texture, pos.zyx * 2 + vec3<i32>(offset));
+        let value = textureLoad(from_texture, pos * 2 + vec3<i32>(offset), 0);
+        materials[i] = textureLoad(from_material, pos * 2 + vec3<i32>(offset), 0).r;
 
         sum += value.rgb * value.a;
         alpha += value.a;
     }
-    sum /= alpha;
-    alpha /= 8.0;
 
-    textureStore(to_texture, pos.zyx, vec4(sum.rgb, alpha));
-}
\ No newline at end of file
+    var material = 0u;
+    var material_count = 0u;
+    for (var i = 0u; i < 8u; i++) {
+        if materials[i] == 0u {
+            continue;
+        }
+        var count = 0u;
+        for (var j = 0u; j < 8u; j++) {
+            count += u32(materials[j] == materials[i]);
+        }
+        if count > material_count {
+            material = materials[i];
+            material_count = count;
+        }
+    }
+
+    textureStore(to_texture, pos, vec4(sum / max(alpha, 0.0001), alpha / 8.0));
+    textureStore(to_material, pos, vec4(material));
+}
@@ -1,22 +1,49 @@
+use crate::{DebrisSettings, IntegritySettings, TraceSettings};
 use bevy::{
-    asset::embedded_asset,
+    asset::{embedded_asset, load_internal_asset},
+    core::FrameCount,
     prelude::*,
     render::{
+        camera::ExtractedCamera,
         extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_graph::RenderGraphContext,
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
         Render, RenderApp, RenderSet,
     },
     utils::HashMap,
 };
+use std::sync::{atomic::AtomicU32, Arc};
 
 pub mod animation;
 pub mod automata;
 pub mod clear;
+pub mod integrity;
+pub mod light_volume;
+pub mod mip;
+pub mod navigation;
+pub mod particles;
 pub mod physics;
 pub mod rebuild;
 
+const COLLISION_HANDLE: Handle<Shader> = Handle::weak_from_u128(8350571726389273914);
+
 const MAX_TYPE_BUFFER_DATA: usize = 1000000; // 4mb
+pub const MAX_PARTICLES: u32 = 65536;
+const PARTICLE_SIZE: u64 = 64;
+const PARTICLE_HEADER_SIZE: u64 = 16;
+pub const MAX_EDITS: u32 = 64;
+const EDIT_SIZE: u64 = 16;
+const EDIT_HEADER_SIZE: u64 = 16;
+const EDIT_DISPATCH_SIZE: u64 = 16;
+pub const NAVIGATION_CHUNKS_PER_FRAME: u32 = 2048;
+pub const NAVIGATION_CHUNK_WORDS: usize = 16;
+// the navigation readback is only started again once the last one arrived
+pub const READBACK_IDLE: u32 = 0;
+pub const READBACK_COPIED: u32 = 1;
+pub const READBACK_MAPPING: u32 = 2;
+pub const READBACK_MAPPED: u32 = 3;
+const MAX_NAVIGATION_CHUNKS: usize = 32768; // enough for a 256^3 world
 
 pub struct ComputeResourcesPlugin;
 
@@ -27,6 +54,11 @@ impl Plugin for ComputeResourcesPlugin {
             embedded_asset!(app, "src/", "animation.wgsl");
             embedded_asset!(app, "src/", "automata.wgsl");
             embedded_asset!(app, "src/", "clear.wgsl");
+            embedded_asset!(app, "src/", "integrity.wgsl");
+            embedded_asset!(app, "src/", "light_volume.wgsl");
+            embedded_asset!(app, "src/", "mip.wgsl");
+            embedded_asset!(app, "src/", "navigation.wgsl");
+            embedded_asset!(app, "src/", "particles.wgsl");
             embedded_asset!(app, "src/", "physics.wgsl");
             embedded_asset!(app, "src/", "rebuild.wgsl");
         }
@@ -35,9 +67,21 @@ impl Plugin for ComputeResourcesPlugin {
             embedded_asset!(app, "src\\", "animation.wgsl");
             embedded_asset!(app, "src\\", "automata.wgsl");
             embedded_asset!(app, "src\\", "clear.wgsl");
+            embedded_asset!(app, "src\\", "integrity.wgsl");
+            embedded_asset!(app, "src\\", "light_volume.wgsl");
+            embedded_asset!(app, "src\\", "mip.wgsl");
+            embedded_asset!(app, "src\\", "navigation.wgsl");
+            embedded_asset!(app, "src\\", "particles.wgsl");
             embedded_asset!(app, "src\\", "physics.wgsl");
             embedded_asset!(app, "src\\", "rebuild.wgsl");
         }
+
+        load_internal_asset!(app, COLLISION_HANDLE, "collision.wgsl", Shader::from_wgsl);
+
+        app.init_resource::<DebrisSettings>()
+            .init_resource::<IntegritySettings>()
+            .add_plugins(ExtractResourcePlugin::<DebrisSettings>::default())
+            .add_plugins(ExtractResourcePlugin::<IntegritySettings>::default());
     }
 
     fn finish(&self, app: &mut App) {
@@ -48,6 +92,12 @@ impl Plugin for ComputeResourcesPlugin {
         let mut uniform_buffer = UniformBuffer::from(ComputeUniforms {
             time: 0.0,
             delta_time: 0.0,
+            debris_lifetime: 0.0,
+            debris_speed: 0.0,
+            debris_gravity: Vec3::ZERO,
+            debris_settle: 0,
+            integrity_collapse: 0,
+            frame_count: 0,
         });
         uniform_buffer.write_buffer(&render_device, &render_queue);
 
@@ -66,6 +116,52 @@ impl Plugin for ComputeResourcesPlugin {
             label: None,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
         });
+        let emitter_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: bytemuck::cast_slice(&vec![0u32; MAX_TYPE_BUFFER_DATA]),
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        // particles live entirely on the gpu, a zeroed particle is a dead particle
+        let particle_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: &vec![
+                0u8;
+                (PARTICLE_HEADER_SIZE + PARTICLE_SIZE * MAX_PARTICLES as u64) as usize
+            ],
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        // destroyed voxel positions for the integrity pass, cleared every frame
+        let edit_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: &vec![0u8; (EDIT_HEADER_SIZE + EDIT_SIZE * MAX_EDITS as u64) as usize],
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        // indirect dispatch arguments for every edit slot, filled on the gpu from the edit count
+        let edit_dispatch_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: &vec![0u8; (EDIT_DISPATCH_SIZE * MAX_EDITS as u64) as usize],
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+        });
+
+        // the collision bits of every chunk as last sent to the cpu
+        let navigation_chunks = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: &vec![0u8; MAX_NAVIGATION_CHUNKS * NAVIGATION_CHUNK_WORDS * 4],
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        // chunks that changed this frame, a count followed by the index and bits of each chunk
+        let navigation_buffer_size =
+            4 + 4 * (NAVIGATION_CHUNK_WORDS + 1) * NAVIGATION_CHUNKS_PER_FRAME as usize;
+        let navigation_buffer_gpu = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: &vec![0u8; navigation_buffer_size],
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        });
+        let navigation_buffer_cpu = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: &vec![0u8; navigation_buffer_size],
+            label: None,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        });
 
         let bind_group_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -101,6 +197,66 @@ impl Plugin for ComputeResourcesPlugin {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(PARTICLE_HEADER_SIZE + PARTICLE_SIZE),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(EDIT_HEADER_SIZE + EDIT_SIZE),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(8),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(EDIT_DISPATCH_SIZE),
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -120,6 +276,30 @@ impl Plugin for ComputeResourcesPlugin {
                     binding: 2,
                     resource: animation_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: emitter_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: edit_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: navigation_chunks.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: navigation_buffer_gpu.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: edit_dispatch_buffer.as_entire_binding(),
+                },
             ],
         );
 
@@ -134,8 +314,21 @@ impl Plugin for ComputeResourcesPlugin {
             dispatch_size: 0,
             animation_buffer,
         })
+        .insert_resource(EmitterData {
+            dispatch_size: 0,
+            emitter_buffer,
+        })
+        .insert_resource(NavigationData {
+            navigation_buffer_gpu,
+            navigation_buffer_cpu,
+            readback: Arc::new(AtomicU32::new(READBACK_IDLE)),
+            world: 0,
+            readback_world: Arc::new(AtomicU32::new(0)),
+        })
         .add_plugins(ExtractResourcePlugin::<PhysicsData>::default())
-        .add_plugins(ExtractResourcePlugin::<AnimationData>::default());
+        .add_plugins(ExtractResourcePlugin::<AnimationData>::default())
+        .add_plugins(ExtractResourcePlugin::<EmitterData>::default())
+        .add_plugins(ExtractResourcePlugin::<NavigationData>::default());
 
         let render_app = app.sub_app_mut(RenderApp);
 
@@ -144,18 +337,38 @@ impl Plugin for ComputeResourcesPlugin {
                 bind_group_layout,
                 bind_group,
                 uniform_buffer,
+                edit_buffer,
+                edit_dispatch_buffer,
+                navigation_chunks,
             })
             .init_resource::<clear::Pipeline>()
             .init_resource::<rebuild::Pipeline>()
             .init_resource::<automata::Pipeline>()
             .init_resource::<physics::Pipeline>()
             .init_resource::<animation::Pipeline>()
-            .add_systems(Render, prepare_uniforms.in_set(RenderSet::Prepare));
+            .init_resource::<particles::Pipeline>()
+            .init_resource::<integrity::Pipeline>()
+            .init_resource::<navigation::Pipeline>()
+            .init_resource::<light_volume::LightVolume>()
+            .init_resource::<light_volume::Pipeline>()
+            .init_resource::<mip::Pipeline>()
+            .init_resource::<ComputeView>()
+            .add_systems(
+                Render,
+                (prepare_uniforms, prepare_compute_view).in_set(RenderSet::Prepare),
+            )
+            .add_systems(
+                Render,
+                navigation::map_navigation_data.in_set(RenderSet::Cleanup),
+            );
     }
 }
 
 fn prepare_uniforms(
     time: Res<Time>,
+    debris_settings: Res<DebrisSettings>,
+    integrity_settings: Res<IntegritySettings>,
+    frame_count: Res<FrameCount>,
     mut compute_data: ResMut<ComputeData>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
@@ -163,6 +376,12 @@ fn prepare_uniforms(
     let uniforms = ComputeUniforms {
         time: time.elapsed_seconds_f64() as f32,
         delta_time: time.delta_seconds() as f32,
+        debris_lifetime: debris_settings.lifetime,
+        debris_speed: debris_settings.speed,
+        debris_gravity: debris_settings.gravity,
+        debris_settle: debris_settings.settle as u32,
+        integrity_collapse: integrity_settings.collapse as u32,
+        frame_count: frame_count.0,
     };
     compute_data.uniform_buffer.set(uniforms);
     compute_data
@@ -170,10 +389,39 @@ fn prepare_uniforms(
         .write_buffer(&render_device, &render_queue);
 }
 
+/// The traced view the per view compute nodes run for. Every other voxel
+/// camera sees the same world, so it is only rebuilt and simulated once a frame.
+#[derive(Resource, Default)]
+struct ComputeView(Option<Entity>);
+
+/// Whether a per view compute node should run for the view of `graph`.
+/// Cameras in the core 3d graph without `VoxelRender` don't trace the world,
+/// and the other voxel cameras share the result of the compute view.
+pub fn is_compute_view(graph: &RenderGraphContext, world: &World) -> bool {
+    world.resource::<ComputeView>().0 == Some(graph.view_entity())
+}
+
+fn prepare_compute_view(
+    mut compute_view: ResMut<ComputeView>,
+    query: Query<(Entity, &ExtractedCamera), With<TraceSettings>>,
+) {
+    // the first camera to render
+    compute_view.0 = query
+        .iter()
+        .min_by_key(|(_, camera)| camera.order)
+        .map(|(entity, _)| entity);
+}
+
 #[derive(Resource, ShaderType)]
 struct ComputeUniforms {
     time: f32,
     delta_time: f32,
+    debris_lifetime: f32,
+    debris_speed: f32,
+    debris_gravity: Vec3,
+    debris_settle: u32,
+    integrity_collapse: u32,
+    frame_count: u32,
 }
 
 #[derive(Clone, Resource, ExtractResource)]
@@ -191,9 +439,30 @@ pub struct AnimationData {
     pub animation_buffer: Buffer,
 }
 
+#[derive(Clone, Resource, ExtractResource)]
+pub struct EmitterData {
+    pub dispatch_size: u32,
+    pub emitter_buffer: Buffer,
+}
+
+#[derive(Clone, Resource, ExtractResource)]
+pub struct NavigationData {
+    pub navigation_buffer_gpu: Buffer,
+    pub navigation_buffer_cpu: Buffer,
+    /// where the cpu buffer is in its round trip, one of the `READBACK_` states
+    pub readback: Arc<AtomicU32>,
+    /// counts up with every new world
+    pub world: u32,
+    /// the `world` the chunks in the cpu buffer were read from
+    pub readback_world: Arc<AtomicU32>,
+}
+
 #[derive(Resource)]
 pub struct ComputeData {
     pub bind_group_layout: BindGroupLayout,
     pub bind_group: BindGroup,
     uniform_buffer: UniformBuffer<ComputeUniforms>,
+    edit_buffer: Buffer,
+    edit_dispatch_buffer: Buffer,
+    navigation_chunks: Buffer,
 }
@@ -0,0 +1,137 @@
+use super::{
+    ComputeData, NavigationData, NAVIGATION_CHUNKS_PER_FRAME, READBACK_COPIED, READBACK_IDLE,
+    READBACK_MAPPED, READBACK_MAPPING,
+};
+use crate::{
+    voxel_pipeline::voxel_world::{NewGH, VoxelData},
+    RenderGraphSettings,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::RenderContext,
+    },
+};
+use std::{borrow::Cow, sync::atomic::Ordering};
+
+pub struct NavigationNode;
+
+#[derive(Resource)]
+pub struct Pipeline(CachedComputePipelineId);
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let compute_bind_group_layout = world.resource::<ComputeData>().bind_group_layout.clone();
+
+        let asset_server = world.resource::<AssetServer>();
+        let shader = asset_server
+            .load("embedded://bevy_voxel_engine/voxel_pipeline/compute/navigation.wgsl");
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+        let navigation_pipeline =
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("navigation pipeline")),
+                layout: vec![voxel_bind_group_layout, compute_bind_group_layout],
+                shader,
+                shader_defs: vec![],
+                entry_point: Cow::from("navigation"),
+                push_constant_ranges: vec![],
+            });
+
+        Pipeline(navigation_pipeline)
+    }
+}
+
+impl render_graph::Node for NavigationNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let voxel_data = world.resource::<VoxelData>();
+        let compute_data = world.resource::<ComputeData>();
+        let navigation_data = world.resource::<NavigationData>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_graph_settings = world.resource::<RenderGraphSettings>();
+
+        if !render_graph_settings.navigation {
+            return Ok(());
+        }
+
+        let pipeline = match pipeline_cache.get_compute_pipeline(world.resource::<Pipeline>().0) {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+
+        let command_encoder = render_context.command_encoder();
+
+        // a new world starts out empty on the cpu, so every chunk gets sent again
+        if let NewGH::Some(_) = world.resource::<NewGH>() {
+            command_encoder.clear_buffer(&compute_data.navigation_chunks, 0, None);
+        }
+
+        // wait for the cpu to read the last chunks before sending more
+        if navigation_data.readback.load(Ordering::Acquire) != READBACK_IDLE {
+            return Ok(());
+        }
+        command_encoder.clear_buffer(
+            &navigation_data.navigation_buffer_gpu,
+            0,
+            BufferSize::new(4),
+        );
+
+        {
+            let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+
+            pass.set_bind_group(0, &voxel_data.bind_group, &[]);
+            pass.set_bind_group(1, &compute_data.bind_group, &[]);
+
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(NAVIGATION_CHUNKS_PER_FRAME, 1, 1);
+        }
+
+        command_encoder.copy_buffer_to_buffer(
+            &navigation_data.navigation_buffer_gpu,
+            0,
+            &navigation_data.navigation_buffer_cpu,
+            0,
+            navigation_data.navigation_buffer_gpu.size(),
+        );
+        navigation_data
+            .readback_world
+            .store(navigation_data.world, Ordering::Relaxed);
+        navigation_data
+            .readback
+            .store(READBACK_COPIED, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+/// Starts mapping the navigation data once the copy has been submitted, the
+/// main world reads it whenever it is ready
+pub fn map_navigation_data(navigation_data: Res<NavigationData>) {
+    if navigation_data.readback.load(Ordering::Acquire) != READBACK_COPIED {
+        return;
+    }
+
+    navigation_data
+        .readback
+        .store(READBACK_MAPPING, Ordering::Release);
+    let readback = navigation_data.readback.clone();
+    navigation_data
+        .navigation_buffer_cpu
+        .slice(..)
+        .map_async(MapMode::Read, move |result| {
+            // a failed map starts over on the next frame
+            let state = match result {
+                Ok(()) => READBACK_MAPPED,
+                Err(_) => READBACK_IDLE,
+            };
+            readback.store(state, Ordering::Release);
+        });
+}
@@ -0,0 +1,104 @@
+#import bevy_voxel_engine::common::{
+    VoxelUniforms,
+    COLLISION_FLAG,
+    ANIMATION_FLAG,
+    PORTAL_FLAG,
+}
+#import bevy_voxel_engine::bindings::{
+    voxel_world,
+    voxel_uniforms,
+    gh
+}
+
+struct ComputeUniforms {
+    time: f32,
+    delta_time: f32,
+    debris_lifetime: f32,
+    debris_speed: f32,
+    debris_gravity: vec3<f32>,
+    debris_settle: u32,
+    integrity_collapse: u32,
+    frame_count: u32,
+}
+
+struct NavigationUpdates {
+    count: atomic<u32>,
+    // chunk index followed by 16 words of collision bits
+    data: array<u32>,
+}
+
+@group(1) @binding(0)
+var<uniform> compute_uniforms: ComputeUniforms;
+@group(1) @binding(6)
+var<storage, read_write> navigation_chunks: array<u32>;
+@group(1) @binding(7)
+var<storage, read_write> navigation_updates: NavigationUpdates;
+
+const CHUNK_SIZE = 8;
+const CHUNK_WORDS = 16u;
+const NAVIGATION_CHUNKS_PER_FRAME = 2048u;
+
+var<workgroup> chunk_bits: array<atomic<u32>, 16>;
+
+fn is_solid(pos: vec3<i32>) -> bool {
+    let voxel = textureLoad(voxel_world, pos.zyx).r;
+    let flags = voxel >> 8u;
+    return (voxel & 0xFFu) != 0u
+        && (flags & COLLISION_FLAG) != 0u
+        && (flags & (ANIMATION_FLAG | PORTAL_FLAG)) == 0u;
+}
+
+// Every workgroup checks one chunk of the world and sends it back to the cpu
+// when it differs from the last time it was checked.
+@compute @workgroup_size(8, 8, 4)
+fn navigation(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let chunks_per_side = voxel_uniforms.texture_size / u32(CHUNK_SIZE);
+    let chunk_count = min(chunks_per_side * chunks_per_side * chunks_per_side, arrayLength(&navigation_chunks) / CHUNK_WORDS);
+    if (workgroup_id.x >= chunk_count) {
+        return;
+    }
+
+    // a different part of the world is checked every frame
+    let offset = (compute_uniforms.frame_count % chunk_count) * NAVIGATION_CHUNKS_PER_FRAME;
+    let chunk = (offset + workgroup_id.x) % chunk_count;
+    let chunk_pos = vec3(
+        chunk % chunks_per_side,
+        (chunk / chunks_per_side) % chunks_per_side,
+        chunk / (chunks_per_side * chunks_per_side),
+    );
+
+    if (local_index < CHUNK_WORDS) {
+        atomicStore(&chunk_bits[local_index], 0u);
+    }
+    workgroupBarrier();
+
+    for (var z = 0u; z < 2u; z++) {
+        let local_pos = local_id + vec3(0u, 0u, z * 4u);
+        if (is_solid(vec3<i32>(chunk_pos * u32(CHUNK_SIZE) + local_pos))) {
+            let bit = local_pos.x + local_pos.y * 8u + local_pos.z * 64u;
+            atomicOr(&chunk_bits[bit / 32u], 1u << (bit % 32u));
+        }
+    }
+    workgroupBarrier();
+
+    if (local_index == 0u) {
+        var changed = false;
+        for (var i = 0u; i < CHUNK_WORDS; i++) {
+            let bits = atomicLoad(&chunk_bits[i]);
+            changed = changed || bits != navigation_chunks[chunk * CHUNK_WORDS + i];
+            navigation_chunks[chunk * CHUNK_WORDS + i] = bits;
+        }
+
+        if (changed) {
+            let index = atomicAdd(&navigation_updates.count, 1u) * (CHUNK_WORDS + 1u);
+            navigation_updates.data[index] = chunk;
+            for (var i = 0u; i < CHUNK_WORDS; i++) {
+                navigation_updates.data[index + 1u + i] = atomicLoad(&chunk_bits[i]);
+            }
+        }
+    }
+}
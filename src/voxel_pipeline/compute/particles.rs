@@ -0,0 +1,105 @@
+use super::{ComputeData, EmitterData, MAX_PARTICLES};
+use crate::{voxel_pipeline::voxel_world::VoxelData, RenderGraphSettings};
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::RenderContext,
+    },
+};
+use std::borrow::Cow;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+pub struct ParticleNode;
+
+#[derive(Resource)]
+pub struct Pipeline {
+    emit_pipeline: CachedComputePipelineId,
+    simulate_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let compute_bind_group_layout = world.resource::<ComputeData>().bind_group_layout.clone();
+
+        let asset_server = world.resource::<AssetServer>();
+        let shader =
+            asset_server.load("embedded://bevy_voxel_engine/voxel_pipeline/compute/particles.wgsl");
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+        let emit_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("particle emit pipeline")),
+            layout: vec![
+                voxel_bind_group_layout.clone(),
+                compute_bind_group_layout.clone(),
+            ],
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from("emit"),
+            push_constant_ranges: vec![],
+        });
+        let simulate_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("particle simulate pipeline")),
+            layout: vec![voxel_bind_group_layout, compute_bind_group_layout],
+            shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("simulate"),
+            push_constant_ranges: vec![],
+        });
+
+        Pipeline {
+            emit_pipeline,
+            simulate_pipeline,
+        }
+    }
+}
+
+impl render_graph::Node for ParticleNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let voxel_data = world.resource::<VoxelData>();
+        let compute_data = world.resource::<ComputeData>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let emitter_data = world.resource::<EmitterData>();
+        let render_graph_settings = world.resource::<RenderGraphSettings>();
+        let pipelines = world.resource::<Pipeline>();
+
+        if !render_graph_settings.particles {
+            return Ok(());
+        }
+
+        let (emit_pipeline, simulate_pipeline) = match (
+            pipeline_cache.get_compute_pipeline(pipelines.emit_pipeline),
+            pipeline_cache.get_compute_pipeline(pipelines.simulate_pipeline),
+        ) {
+            (Some(emit_pipeline), Some(simulate_pipeline)) => (emit_pipeline, simulate_pipeline),
+            _ => return Ok(()),
+        };
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        pass.set_bind_group(0, &voxel_data.bind_group, &[]);
+        pass.set_bind_group(1, &compute_data.bind_group, &[]);
+
+        // Spawn new particles from the emitters
+        let dispatch_size = (emitter_data.dispatch_size as f32).cbrt().ceil() as u32;
+        if dispatch_size > 0 {
+            pass.set_pipeline(emit_pipeline);
+            pass.dispatch_workgroups(dispatch_size, dispatch_size, dispatch_size);
+        }
+
+        pass.set_pipeline(simulate_pipeline);
+        pass.dispatch_workgroups(MAX_PARTICLES / WORKGROUP_SIZE, 1, 1);
+
+        Ok(())
+    }
+}
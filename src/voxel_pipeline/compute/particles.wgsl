@@ -0,0 +1,170 @@
+#import bevy_voxel_engine::common::{
+    VOXELS_PER_METER,
+    PI,
+    VoxelUniforms,
+    Ray,
+    Particle,
+    COLLISION_FLAG,
+    ANIMATION_FLAG,
+    hash,
+}
+#import bevy_voxel_engine::raytracing::shoot_ray
+#import bevy_voxel_engine::bindings::{
+    voxel_world,
+    voxel_uniforms,
+    gh
+}
+#import bevy_voxel_engine::collision::{
+    compute_uniforms,
+    particles,
+    in_texture_bounds,
+    spawn_particle,
+    apply_collision_effect,
+}
+
+@group(1) @binding(4)
+var<storage, read> emitter_data: array<u32>;
+
+fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
+    let texture_value = textureLoad(voxel_world, pos.zyx).r;
+    return vec2(
+        texture_value & 0xFFu,
+        texture_value >> 8u,
+    );
+}
+
+fn write_pos(pos: vec3<i32>, material: u32, flags: u32) {
+    let voxel_type = get_texture_value(pos);
+    if (in_texture_bounds(pos) && voxel_type.x == 0u) {
+        textureStore(voxel_world, pos.zyx, vec4(material | (flags << 8u)));
+    }
+}
+
+// random direction inside a cone around dir
+fn cone_direction(dir: vec3<f32>, angle: f32, seed: vec3<u32>) -> vec3<f32> {
+    let u = hash(seed);
+
+    let cos_theta = mix(1.0, cos(angle), u.x);
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let phi = 2.0 * PI * u.y;
+
+    var up = vec3(0.0, 1.0, 0.0);
+    if (abs(dir.y) > 0.999) {
+        up = vec3(1.0, 0.0, 0.0);
+    }
+    let b = normalize(cross(dir, up));
+    let t = cross(b, dir);
+
+    return normalize(sin_theta * cos(phi) * b + sin_theta * sin(phi) * t + cos_theta * dir);
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn emit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let header_len = i32(emitter_data[0]);
+    let dispatch_size = i32(ceil(pow(f32(header_len), 1.0 / 3.0)));
+
+    let pos = vec3(i32(invocation_id.x), i32(invocation_id.y), i32(invocation_id.z));
+    let index = pos.x * dispatch_size * dispatch_size + pos.y * dispatch_size + pos.z + 1;
+
+    if (index <= header_len) {
+        let data_index = i32(u32(emitter_data[index]) & 0x00FFFFFFu);
+
+        let position = vec3(
+            bitcast<f32>(emitter_data[data_index + 0]),
+            bitcast<f32>(emitter_data[data_index + 1]),
+            bitcast<f32>(emitter_data[data_index + 2]),
+        );
+        let direction = vec3(
+            bitcast<f32>(emitter_data[data_index + 3]),
+            bitcast<f32>(emitter_data[data_index + 4]),
+            bitcast<f32>(emitter_data[data_index + 5]),
+        );
+        let gravity = vec3(
+            bitcast<f32>(emitter_data[data_index + 6]),
+            bitcast<f32>(emitter_data[data_index + 7]),
+            bitcast<f32>(emitter_data[data_index + 8]),
+        );
+        let collision_effect = vec3(
+            bitcast<f32>(emitter_data[data_index + 9]),
+            bitcast<f32>(emitter_data[data_index + 10]),
+            bitcast<f32>(emitter_data[data_index + 11]),
+        );
+        let count = emitter_data[data_index + 12];
+        let cone_angle = bitcast<f32>(emitter_data[data_index + 13]);
+        let speed = bitcast<f32>(emitter_data[data_index + 14]);
+        let lifetime = bitcast<f32>(emitter_data[data_index + 15]);
+        let voxel = emitter_data[data_index + 16];
+        let settle = emitter_data[data_index + 17];
+
+        let time_seed = u32(compute_uniforms.time * 1000.0);
+        for (var i = 0u; i < count; i++) {
+            let seed = vec3(i, u32(index), time_seed);
+            let velocity = cone_direction(direction, cone_angle, seed) * speed;
+            spawn_particle(Particle(
+                position,
+                lifetime,
+                velocity,
+                voxel,
+                gravity,
+                settle,
+                collision_effect,
+            ));
+        }
+    }
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn simulate(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    if (index >= arrayLength(&particles.particles)) {
+        return;
+    }
+
+    var particle = particles.particles[index];
+    if (particle.life <= 0.0) {
+        return;
+    }
+
+    let delta_time = compute_uniforms.delta_time;
+    particle.life -= delta_time;
+    particle.velocity += particle.gravity * delta_time;
+
+    var resting = false;
+    if (any(abs(particle.velocity) > vec3(0.0001))) {
+        let direction = Ray(particle.position, normalize(particle.velocity));
+        let distance = length(particle.velocity) * delta_time;
+        let hit = shoot_ray(direction, distance, COLLISION_FLAG);
+        particle.position = hit.pos;
+        particle.velocity = (hit.portals * vec4(particle.velocity, 0.0)).xyz;
+
+        if (hit.hit) {
+            if (particle.collision_effect.x != 0.0) {
+                let texture_coords = vec3<i32>(floor(particle.position * VOXELS_PER_METER + vec3(f32(voxel_uniforms.texture_size) / 2.0)));
+                apply_collision_effect(texture_coords, particle.position, hit.normal, particle.collision_effect);
+                particles.particles[index].life = 0.0;
+                return;
+            }
+
+            // lose most of the energy on every bounce
+            particle.velocity = reflect(particle.velocity, hit.normal) * 0.3;
+            resting = length(particle.velocity) < 1.0 && hit.normal.y > 0.5;
+        }
+    } else {
+        resting = true;
+    }
+
+    let texture_pos = vec3<i32>(floor(particle.position * VOXELS_PER_METER + vec3(f32(voxel_uniforms.texture_size) / 2.0)));
+    let material = particle.voxel & 0xFFu;
+
+    if (particle.life <= 0.0 || resting) {
+        if (particle.settle != 0u) {
+            write_pos(texture_pos, material, (particle.voxel >> 8u) & ~ANIMATION_FLAG);
+        }
+        particle.life = 0.0;
+    } else {
+        // drawn like the animation data so it gets cleared next frame
+        write_pos(texture_pos, material, ANIMATION_FLAG);
+    }
+
+    particles.particles[index] = particle;
+}
@@ -1,4 +1,4 @@
-use super::{ComputeData, PhysicsData};
+use super::{is_compute_view, ComputeData, PhysicsData};
 use crate::{voxel_pipeline::voxel_world::VoxelData, RenderGraphSettings};
 use bevy::{
     prelude::*,
@@ -40,7 +40,7 @@ impl FromWorld for Pipeline {
 impl render_graph::Node for PhysicsNode {
     fn run(
         &self,
-        _graph: &mut RenderGraphContext,
+        graph: &mut RenderGraphContext,
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), NodeRunError> {
@@ -54,6 +54,10 @@ impl render_graph::Node for PhysicsNode {
             return Ok(());
         }
 
+        if !is_compute_view(graph, world) {
+            return Ok(());
+        }
+
         let pipeline = match pipeline_cache.get_compute_pipeline(world.resource::<Pipeline>().0) {
             Some(pipeline) => pipeline,
             None => return Ok(()),
@@ -13,14 +13,11 @@
     voxel_uniforms,
     gh
 }
-
-struct ComputeUniforms {
-    time: f32,
-    delta_time: f32,
+#import bevy_voxel_engine::collision::{
+    compute_uniforms,
+    apply_collision_effect,
 }
 
-@group(1) @binding(0)
-var<uniform> compute_uniforms: ComputeUniforms;
 @group(1) @binding(1)
 var<storage, read_write> physics_data: array<u32>;
 
@@ -79,41 +76,10 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                     hit_normal = hit.normal;
                     
                     // Collision effects
-
-                    let texture_coords = 
-                        vec3<i32>(world_pos * VOXELS_PER_METER + vec3(f32(voxel_uniforms.texture_size) / 2.0));
-
                     if collision_effect.x != 0.0 {
-                        let radius = collision_effect.y;
-                        let range = i32(ceil(radius * VOXELS_PER_METER));
-                        for (var x = -range; x <= range; x++) {
-                            for (var y = -range; y <= range; y++) {
-                                for (var z = -range; z <= range; z++) {
-                                    let offset = vec3(x, y, z);
-                                    let texture_coords = texture_coords + offset;
-                                    if (length(vec3<f32>(offset) / VOXELS_PER_METER) >= radius) {
-                                        continue;
-                                    }
-
-                                    // Destroy
-                                    if (collision_effect.x == 1.0) {
-                                        textureStore(voxel_world, texture_coords.zyx, vec4(0u));
-                                    }
-                                    // Place
-                                    if (collision_effect.x == 2.0) {
-                                        let material = bitcast<u32>(collision_effect.z);
-                                        textureStore(voxel_world, texture_coords.zyx, vec4(material));
-                                    }
-                                    // Set Flags
-                                    if (collision_effect.x == 3.0) {
-                                        let flags = bitcast<u32>(collision_effect.z);
-                                        var voxel = textureLoad(voxel_world, texture_coords.zyx).r;
-                                        voxel |= flags << 8u;
-                                        textureStore(voxel_world, texture_coords.zyx, vec4(voxel));
-                                    }
-                                }
-                            }
-                        }
+                        let texture_coords =
+                            vec3<i32>(world_pos * VOXELS_PER_METER + vec3(f32(voxel_uniforms.texture_size) / 2.0));
+                        apply_collision_effect(texture_coords, world_pos, hit_normal, collision_effect);
                     }
                 }
             }
@@ -129,6 +95,14 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                     bitcast<i32>(physics_data[data_index + 26]),
                 );
                 let v_sign = sign(velocity);
+                let step_height = i32(physics_data[data_index + 27]);
+
+                // hits below the step height can be stepped over instead of blocking
+                var step_up = 0;
+                var x_wall = false;
+                var x_step = false;
+                var z_wall = false;
+                var z_step = false;
 
                 // x face
                 for (var y = -size.y; y <= size.y; y++) {
@@ -138,7 +112,12 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                         
                         let plane_normal = vec3(1.0, 0.0, 0.0);
                         if (hit.hit && all(abs(hit.normal) == plane_normal)) {
-                            velocity = velocity - dot(velocity, plane_normal) * plane_normal;
+                            if (y < -size.y + step_height) {
+                                step_up = max(step_up, y + size.y + 1);
+                                x_step = true;
+                            } else {
+                                x_wall = true;
+                            }
                             // world_pos = hit.pos - offset;
                         }
                     }
@@ -166,12 +145,44 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                         
                         let plane_normal = vec3(0.0, 0.0, 1.0);
                         if (hit.hit && all(abs(hit.normal) == plane_normal)) {
-                            velocity = velocity - dot(velocity, plane_normal) * plane_normal;
+                            if (y < -size.y + step_height) {
+                                step_up = max(step_up, y + size.y + 1);
+                                z_step = true;
+                            } else {
+                                z_wall = true;
+                            }
                             // world_pos = hit.pos - offset;
                         }
                     }
                 }
 
+                // Step up
+                var stepped = false;
+                if (step_up > 0 && !x_wall && !z_wall && velocity.y <= 0.0) {
+                    // make sure there is room above the box to step into
+                    let step_distance = f32(step_up) / VOXELS_PER_METER;
+                    var blocked = false;
+                    for (var x = -size.x; x <= size.x; x++) {
+                        for (var z = -size.z; z <= size.z; z++) {
+                            let offset = vec3(f32(x), f32(size.y), f32(z)) / (VOXELS_PER_METER * 1.001);
+                            let hit = shoot_ray(Ray((world_pos + offset), vec3(0.0, 1.0, 0.0)), step_distance, COLLISION_FLAG);
+                            blocked = blocked || hit.hit;
+                        }
+                    }
+
+                    if (!blocked) {
+                        world_pos.y += step_distance;
+                        stepped = true;
+                    }
+                }
+
+                if (x_wall || (x_step && !stepped)) {
+                    velocity.x = 0.0;
+                }
+                if (z_wall || (z_step && !stepped)) {
+                    velocity.z = 0.0;
+                }
+
                 if (any(abs(velocity) > vec3(0.01))) {
                     let direction = normalize(velocity * compute_uniforms.delta_time);
                     let distance = length(velocity) * compute_uniforms.delta_time;
@@ -181,6 +192,26 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                     world_pos = hit.pos;
                 }
             }
+
+            // check if there is room above the box to grow into
+            let size = vec3(
+                bitcast<i32>(physics_data[data_index + 24]),
+                bitcast<i32>(physics_data[data_index + 25]),
+                bitcast<i32>(physics_data[data_index + 26]),
+            );
+            let headroom = i32(physics_data[data_index + 28]);
+            var room = true;
+            if (headroom > 0) {
+                let distance = f32(headroom) / VOXELS_PER_METER;
+                for (var x = -size.x; x <= size.x; x++) {
+                    for (var z = -size.z; z <= size.z; z++) {
+                        let offset = vec3(f32(x), f32(size.y), f32(z)) / (VOXELS_PER_METER * 1.001);
+                        let hit = shoot_ray(Ray((world_pos + offset), vec3(0.0, 1.0, 0.0)), distance, COLLISION_FLAG);
+                        room = room && !hit.hit;
+                    }
+                }
+            }
+            physics_data[data_index + 28] = u32(room);
         }
 
         physics_data[data_index + 0] = bitcast<u32>(world_pos.x);
@@ -1,3 +1,4 @@
+use super::is_compute_view;
 use crate::{
     load::GH,
     voxel_pipeline::voxel_world::{VoxelData, VoxelUniforms},
@@ -42,7 +43,7 @@ impl FromWorld for Pipeline {
 impl render_graph::Node for RebuildNode {
     fn run(
         &self,
-        _graph: &mut RenderGraphContext,
+        graph: &mut RenderGraphContext,
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), NodeRunError> {
@@ -57,6 +58,10 @@ impl render_graph::Node for RebuildNode {
             return Ok(());
         }
 
+        if !is_compute_view(graph, world) {
+            return Ok(());
+        }
+
         let mut levels = [0; 8];
         for i in 0..8 {
             levels[i] = voxel_uniforms.levels[i].x;
@@ -2,21 +2,19 @@ use bevy::{
     asset::load_internal_asset,
     core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
     prelude::*,
-    reflect::TypeUuid,
     render::{
-        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
         view::ViewTarget,
-        RenderApp, RenderSet,
+        Render, RenderApp, RenderSet,
     },
 };
 pub use node::DenoiseNode;
 
 mod node;
 
-pub const DENOISE_SHADER_HANDLE: HandleUntyped =
-    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 3278741884048607584);
+const DENOISE_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(3278741884048607584);
 
 pub struct DenoisePlugin;
 
@@ -29,45 +27,86 @@ impl Plugin for DenoisePlugin {
             Shader::from_wgsl
         );
 
-        let pass_settings = [
-            DenoisePassData::new(1.0, 0.08, 0.5, 0.1),
-            DenoisePassData::new(2.0, 0.025, 0.5, 0.1),
-            DenoisePassData::new(4.0, 0.015, 0.5, 0.1),
-            // DenoisePassData::new(1.0, 0.15, 0.5, 0.1),
-            // DenoisePassData::new(2.0, 0.10, 0.5, 0.1),
-            // DenoisePassData::new(0.0, 1.0, 1.0, 1.0),
-        ];
-
-        app.insert_resource(DenoiseSettings { pass_settings })
-            .add_plugin(ExtractResourcePlugin::<DenoiseSettings>::default());
+        app.add_plugins(ExtractComponentPlugin::<DenoiseSettings>::default());
+    }
 
+    fn finish(&self, app: &mut App) {
         app.sub_app_mut(RenderApp)
             .init_resource::<DenoisePipeline>()
-            .add_system(prepare_pass_data.in_set(RenderSet::Prepare));
+            .add_systems(Render, prepare_pass_data.in_set(RenderSet::Prepare));
     }
 }
 
-#[derive(Resource, Clone, ExtractResource)]
+/// Edge avoiding à-trous filter over the traced image, used to smooth out the
+/// noise of the indirect lighting. Only runs when `TraceSettings::indirect_bounces`
+/// is not 0.
+#[derive(Component, Clone, ExtractComponent)]
 pub struct DenoiseSettings {
-    pub pass_settings: [DenoisePassData; 3],
+    pub enabled: bool,
+    /// every pass is run one after the other
+    pub passes: Vec<DenoisePassData>,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            passes: vec![
+                DenoisePassData::new(1.0, 0.08, 0.5, 0.1),
+                DenoisePassData::new(2.0, 0.025, 0.5, 0.1),
+                DenoisePassData::new(4.0, 0.015, 0.5, 0.1),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, ShaderType)]
+pub struct DenoisePassData {
+    /// the distance in pixels between the samples
+    pub denoise_strength: f32,
+    /// how quickly samples with a different colour stop counting
+    pub colour_phi: f32,
+    /// how quickly samples with a different normal stop counting
+    pub normal_phi: f32,
+    /// how quickly samples with a different position stop counting
+    pub position_phi: f32,
+}
+
+impl DenoisePassData {
+    pub fn new(denoise_strength: f32, colour_phi: f32, normal_phi: f32, position_phi: f32) -> Self {
+        Self {
+            denoise_strength,
+            colour_phi,
+            normal_phi,
+            position_phi,
+        }
+    }
 }
 
+#[derive(Component)]
+struct ViewDenoisePassOffsets(Vec<u32>);
+
 fn prepare_pass_data(
-    denoise_settings: Res<DenoiseSettings>,
+    mut commands: Commands,
+    query: Query<(Entity, &DenoiseSettings)>,
     mut denoise_pipeline: ResMut<DenoisePipeline>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
 ) {
     denoise_pipeline.pass_data.clear();
-    denoise_pipeline
-        .pass_data
-        .push(denoise_settings.pass_settings[0]);
-    denoise_pipeline
-        .pass_data
-        .push(denoise_settings.pass_settings[1]);
-    denoise_pipeline
-        .pass_data
-        .push(denoise_settings.pass_settings[2]);
+
+    for (entity, denoise_settings) in query.iter() {
+        let offsets = denoise_settings
+            .passes
+            .iter()
+            .map(|pass_data| denoise_pipeline.pass_data.push(*pass_data))
+            .collect();
+
+        commands
+            .entity(entity)
+            .insert(ViewDenoisePassOffsets(offsets));
+    }
+
     denoise_pipeline
         .pass_data
         .write_buffer(&render_device, &render_queue);
@@ -82,9 +121,6 @@ struct DenoisePipeline {
     pass_data: DynamicUniformBuffer<DenoisePassData>,
 }
 
-#[derive(Component)]
-struct ViewDenoisePipeline(CachedRenderPipelineId);
-
 impl FromWorld for DenoisePipeline {
     fn from_world(render_world: &mut World) -> Self {
         let bind_group_layout = render_world
@@ -117,16 +153,6 @@ impl FromWorld for DenoisePipeline {
                     BindGroupLayoutEntry {
                         binding: 2,
                         visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba16Float,
-                            view_dimension: TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::StorageTexture {
                             access: StorageTextureAccess::ReadWrite,
                             format: TextureFormat::Rgba32Float,
@@ -139,7 +165,7 @@ impl FromWorld for DenoisePipeline {
         let pass_data_bind_group_layout = render_world
             .resource::<RenderDevice>()
             .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("denoise bind group layout"),
+                label: Some("denoise pass data bind group layout"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
@@ -172,7 +198,7 @@ impl FromWorld for DenoisePipeline {
             ],
             vertex: fullscreen_shader_vertex_state(),
             fragment: Some(FragmentState {
-                shader: DENOISE_SHADER_HANDLE.typed(),
+                shader: DENOISE_SHADER_HANDLE,
                 shader_defs: vec![],
                 entry_point: "fragment".into(),
                 targets: vec![Some(ColorTargetState {
@@ -210,28 +236,8 @@ impl FromWorld for DenoisePipeline {
     }
 }
 
-#[derive(Clone, Copy, ShaderType)]
-pub struct DenoisePassData {
-    pub denoise_strength: f32,
-    pub colour_phi: f32,
-    pub normal_phi: f32,
-    pub position_phi: f32,
-    padding4: [UVec4; 15],
-}
-impl DenoisePassData {
-    fn new(denoise_strength: f32, colour_phi: f32, normal_phi: f32, position_phi: f32) -> Self {
-        Self {
-            denoise_strength,
-            colour_phi,
-            normal_phi,
-            position_phi,
-            padding4: [UVec4::ZERO; 15],
-        }
-    }
-}
-
 fn get_uniform_buffer_data() -> Vec<u8> {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     let offsets: [(f32, f32); 25] = [
         (-2.0, -2.0), (-1.0, -2.0), (0.0, -2.0), (1.0, -2.0), (2.0, -2.0),
         (-2.0, -1.0), (-1.0, -1.0), (0.0, -1.0), (1.0, -1.0), (2.0, -1.0),
@@ -240,7 +246,7 @@ fn get_uniform_buffer_data() -> Vec<u8> {
         (-2.0, 2.0),  (-1.0, 2.0),  (0.0, 2.0),  (1.0, 2.0),  (2.0, 2.0),
     ];
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     let kernel: [f32; 25] = [
         1.0/256.0, 1.0/64.0, 3.0/128.0, 1.0/64.0, 1.0/256.0,
         1.0/64.0,  1.0/16.0, 3.0/32.0,  1.0/16.0, 1.0/64.0,
@@ -250,14 +256,14 @@ fn get_uniform_buffer_data() -> Vec<u8> {
     ];
 
     let mut data = Vec::new();
-    for i in 0..25 {
-        data.extend_from_slice(&offsets[i].0.to_le_bytes());
-        data.extend_from_slice(&offsets[i].1.to_le_bytes());
+    for offset in offsets {
+        data.extend_from_slice(&offset.0.to_le_bytes());
+        data.extend_from_slice(&offset.1.to_le_bytes());
         data.extend_from_slice(&0i32.to_le_bytes());
         data.extend_from_slice(&0i32.to_le_bytes());
     }
-    for i in 0..25 {
-        data.extend_from_slice(&kernel[i].to_le_bytes());
+    for weight in kernel {
+        data.extend_from_slice(&weight.to_le_bytes());
         data.extend_from_slice(&1f32.to_le_bytes());
         data.extend_from_slice(&1f32.to_le_bytes());
         data.extend_from_slice(&1f32.to_le_bytes());
@@ -0,0 +1,223 @@
+use super::voxel_world::VoxelUniforms;
+use crate::VOXELS_PER_METER;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+};
+
+/// The voxel world is split into this many cells along each axis, every cell
+/// lists the lights that reach into it so only those have to be shaded
+pub const LIGHT_GRID_SIZE: u32 = 16;
+
+pub struct LightsPlugin;
+
+impl Plugin for LightsPlugin {
+    fn build(&self, _app: &mut App) {}
+
+    fn finish(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .init_resource::<ExtractedLights>()
+            .init_resource::<LightData>()
+            .add_systems(ExtractSchedule, extract_lights)
+            .add_systems(Render, prepare_lights.in_set(RenderSet::Prepare));
+    }
+}
+
+/// Light that shines in every direction from the entities translation
+#[derive(Component, Clone, Copy)]
+pub struct VoxelPointLight {
+    pub color: Color,
+    pub intensity: f32,
+    /// distance in meters after which the light has no effect
+    pub range: f32,
+}
+
+impl Default for VoxelPointLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            range: 10.0,
+        }
+    }
+}
+
+/// Light that shines in a cone along the entities forward direction
+#[derive(Component, Clone, Copy)]
+pub struct VoxelSpotLight {
+    pub color: Color,
+    pub intensity: f32,
+    /// distance in meters after which the light has no effect
+    pub range: f32,
+    /// angle in radians from the center of the cone to where the light ends
+    pub outer_angle: f32,
+    /// angle in radians from the center of the cone to where the light starts
+    /// fading out
+    pub inner_angle: f32,
+}
+
+impl Default for VoxelSpotLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            range: 10.0,
+            outer_angle: std::f32::consts::FRAC_PI_4,
+            inner_angle: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ShaderType)]
+struct GpuLight {
+    position: Vec3,
+    range: f32,
+    color: Vec3,
+    cos_outer_angle: f32,
+    direction: Vec3,
+    cos_inner_angle: f32,
+}
+
+#[derive(Default, ShaderType)]
+struct GpuLights {
+    count: u32,
+    #[size(runtime)]
+    lights: Vec<GpuLight>,
+}
+
+#[derive(Resource, Default)]
+struct ExtractedLights(Vec<GpuLight>);
+
+#[derive(Resource)]
+pub struct LightData {
+    lights: StorageBuffer<GpuLights>,
+    /// offset into the indices for every cell followed by the light indices
+    light_grid: StorageBuffer<Vec<u32>>,
+}
+
+impl FromWorld for LightData {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+
+        let mut lights = StorageBuffer::from(GpuLights::default());
+        lights.write_buffer(render_device, render_queue);
+
+        let cells = LIGHT_GRID_SIZE.pow(3) as usize;
+        let mut light_grid = StorageBuffer::from(vec![0; cells + 1]);
+        light_grid.write_buffer(render_device, render_queue);
+
+        Self { lights, light_grid }
+    }
+}
+
+impl LightData {
+    pub fn lights_binding(&self) -> BindingResource<'_> {
+        self.lights.binding().unwrap()
+    }
+
+    pub fn light_grid_binding(&self) -> BindingResource<'_> {
+        self.light_grid.binding().unwrap()
+    }
+}
+
+fn extract_lights(
+    mut extracted_lights: ResMut<ExtractedLights>,
+    point_lights: Extract<Query<(&GlobalTransform, &VoxelPointLight)>>,
+    spot_lights: Extract<Query<(&GlobalTransform, &VoxelSpotLight)>>,
+) {
+    extracted_lights.0.clear();
+
+    for (transform, light) in point_lights.iter() {
+        extracted_lights.0.push(GpuLight {
+            position: transform.translation(),
+            range: light.range,
+            color: Vec4::from(light.color.as_linear_rgba_f32()).truncate() * light.intensity,
+            // every direction is inside of the cone
+            cos_outer_angle: -2.0,
+            direction: Vec3::NEG_Z,
+            cos_inner_angle: -1.0,
+        });
+    }
+
+    for (transform, light) in spot_lights.iter() {
+        extracted_lights.0.push(GpuLight {
+            position: transform.translation(),
+            range: light.range,
+            color: Vec4::from(light.color.as_linear_rgba_f32()).truncate() * light.intensity,
+            cos_outer_angle: light.outer_angle.cos(),
+            direction: transform.forward(),
+            cos_inner_angle: light.inner_angle.min(light.outer_angle).cos(),
+        });
+    }
+}
+
+fn prepare_lights(
+    extracted_lights: Res<ExtractedLights>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    mut light_data: ResMut<LightData>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let lights = &extracted_lights.0;
+
+    // find the cells every light reaches
+    let half_size = voxel_uniforms.texture_size as f32 / (2.0 * VOXELS_PER_METER);
+    let cell_size = 2.0 * half_size / LIGHT_GRID_SIZE as f32;
+    let cell_bounds = |light: &GpuLight| {
+        let to_cell = |pos: Vec3| {
+            ((pos + half_size) / cell_size)
+                .floor()
+                .as_ivec3()
+                .clamp(IVec3::ZERO, IVec3::splat(LIGHT_GRID_SIZE as i32 - 1))
+        };
+        (
+            to_cell(light.position - light.range),
+            to_cell(light.position + light.range),
+        )
+    };
+
+    let cells = LIGHT_GRID_SIZE.pow(3) as usize;
+    let mut cell_lights: Vec<Vec<u32>> = vec![Vec::new(); cells];
+    if cell_size > 0.0 {
+        for (i, light) in lights.iter().enumerate() {
+            let (min, max) = cell_bounds(light);
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        let cell = x as u32
+                            + y as u32 * LIGHT_GRID_SIZE
+                            + z as u32 * LIGHT_GRID_SIZE * LIGHT_GRID_SIZE;
+                        cell_lights[cell as usize].push(i as u32);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut light_grid = Vec::with_capacity(cells + 1);
+    let mut offset = cells as u32 + 1;
+    for indices in cell_lights.iter() {
+        light_grid.push(offset);
+        offset += indices.len() as u32;
+    }
+    light_grid.push(offset);
+    light_grid.extend(cell_lights.into_iter().flatten());
+
+    light_data.lights.set(GpuLights {
+        count: lights.len() as u32,
+        lights: lights.clone(),
+    });
+    light_data
+        .lights
+        .write_buffer(&render_device, &render_queue);
+
+    light_data.light_grid.set(light_grid);
+    light_data
+        .light_grid
+        .write_buffer(&render_device, &render_queue);
+}
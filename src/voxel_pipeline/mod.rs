@@ -1,15 +1,26 @@
 use self::{
     attachments::{AttachmentsNode, AttachmentsPlugin},
     compute::{
-        animation::AnimationNode, automata::AutomataNode, clear::ClearNode, physics::PhysicsNode,
+        animation::AnimationNode, automata::AutomataNode, clear::ClearNode,
+        integrity::IntegrityNode, light_volume::LightVolumeNode, mip::MipNode,
+        navigation::NavigationNode, particles::ParticleNode, physics::PhysicsNode,
         rebuild::RebuildNode, ComputeResourcesPlugin,
     },
-    trace::{TraceNode, TracePlugin},
+    denoise::{DenoiseNode, DenoisePlugin},
+    lights::LightsPlugin,
+    post_process::{BloomNode, PostProcessNode, PostProcessPlugin},
+    trace::{TemporalUpscaleNode, TraceNode, TracePlugin},
     voxel_world::VoxelWorldPlugin,
     voxelization::VoxelizationPlugin,
 };
 use bevy::{
-    core_pipeline::{fxaa::FxaaNode, tonemapping::TonemappingNode, upscaling::UpscalingNode},
+    core_pipeline::{
+        core_3d::{self, MainOpaquePass3dNode, MainTransparentPass3dNode, CORE_3D},
+        fxaa::FxaaNode,
+        tonemapping::TonemappingNode,
+        upscaling::UpscalingNode,
+    },
+    pbr::ShadowPassNode,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
@@ -22,6 +33,9 @@ use bevy::{
 
 pub mod attachments;
 pub mod compute;
+pub mod denoise;
+pub mod lights;
+pub mod post_process;
 pub mod trace;
 pub mod voxel_world;
 pub mod voxelization;
@@ -35,6 +49,9 @@ impl Plugin for RenderPlugin {
             .add_plugins(AttachmentsPlugin)
             .add_plugins(VoxelWorldPlugin)
             .add_plugins(TracePlugin)
+            .add_plugins(LightsPlugin)
+            .add_plugins(DenoisePlugin)
+            .add_plugins(PostProcessPlugin)
             .add_plugins(VoxelizationPlugin)
             .add_plugins(ComputeResourcesPlugin);
     }
@@ -49,7 +66,11 @@ impl Plugin for RenderPlugin {
         // Voxel render graph
         let attachments = AttachmentsNode::new(render_world);
         let trace = TraceNode::new(render_world);
-        //let bloom = BloomNode::new(&mut render_app.world);
+        let denoise = DenoiseNode::new(render_world);
+        let temporal_upscale = TemporalUpscaleNode::new(render_world);
+        let shadow_pass = ShadowPassNode::new(render_world);
+        let bloom = BloomNode::new(render_world);
+        let post_process = PostProcessNode::new(render_world);
         let tonemapping = TonemappingNode::from_world(render_world);
         let fxaa = FxaaNode::from_world(render_world);
         let ui = UiPassNode::new(render_world);
@@ -57,6 +78,19 @@ impl Plugin for RenderPlugin {
 
         voxel_graph.add_node("attachments", attachments);
         voxel_graph.add_node("trace", trace);
+        voxel_graph.add_node("denoise", denoise);
+        voxel_graph.add_node("temporal_upscale", temporal_upscale);
+        voxel_graph.add_node("shadow_pass", shadow_pass);
+        voxel_graph.add_node(
+            "opaque",
+            ViewNodeRunner::new(MainOpaquePass3dNode, render_world),
+        );
+        voxel_graph.add_node(
+            "transparent",
+            ViewNodeRunner::new(MainTransparentPass3dNode, render_world),
+        );
+        voxel_graph.add_node("bloom", bloom);
+        voxel_graph.add_node("post_process", post_process);
         voxel_graph.add_node(
             "tonemapping",
             ViewNodeRunner::new(tonemapping, render_world),
@@ -65,13 +99,39 @@ impl Plugin for RenderPlugin {
         voxel_graph.add_node("ui", ui);
         voxel_graph.add_node("upscaling", ViewNodeRunner::new(upscaling, render_world));
 
-        voxel_graph.add_node_edge("trace", "tonemapping");
+        voxel_graph.add_node_edge("trace", "denoise");
+        // meshes are drawn over the traced image, depth tested against the voxels
+        voxel_graph.add_node_edge("denoise", "temporal_upscale");
+        voxel_graph.add_node_edge("temporal_upscale", "opaque");
+        voxel_graph.add_node_edge("shadow_pass", "opaque");
+        voxel_graph.add_node_edge("opaque", "transparent");
+        voxel_graph.add_node_edge("transparent", "bloom");
+        voxel_graph.add_node_edge("bloom", "post_process");
+        voxel_graph.add_node_edge("post_process", "tonemapping");
         voxel_graph.add_node_edge("tonemapping", "fxaa");
         voxel_graph.add_node_edge("fxaa", "ui");
         voxel_graph.add_node_edge("ui", "upscaling");
 
-        voxel_graph.add_slot_edge("attachments", "normal", "trace", "normal");
-        voxel_graph.add_slot_edge("attachments", "position", "trace", "position");
+        for slot in [
+            "normal",
+            "position",
+            "last_normal",
+            "last_position",
+            "accumulation",
+        ] {
+            voxel_graph.add_slot_edge("attachments", slot, "trace", slot);
+        }
+        voxel_graph.add_slot_edge("attachments", "normal", "denoise", "normal");
+        voxel_graph.add_slot_edge("attachments", "position", "denoise", "position");
+        for slot in [
+            "normal",
+            "position",
+            "last_normal",
+            "last_position",
+            "history",
+        ] {
+            voxel_graph.add_slot_edge("attachments", slot, "temporal_upscale", slot);
+        }
 
         // Voxel render graph compute
         voxel_graph.add_node("rebuild", RebuildNode);
@@ -80,16 +140,77 @@ impl Plugin for RenderPlugin {
         voxel_graph.add_node_edge("rebuild", "physics");
         voxel_graph.add_node_edge("physics", "trace");
 
-        // Main graph compute
+        // The same trace inside bevy's core 3d graph for cameras with `VoxelRender`,
+        // it runs before the main pass so meshes are drawn on top
+        let attachments = AttachmentsNode::new(render_world);
+        let trace = TraceNode::new(render_world);
+        let denoise = DenoiseNode::new(render_world);
+        let temporal_upscale = TemporalUpscaleNode::new(render_world);
+        let post_process = PostProcessNode::new(render_world);
+
         let mut graph = render_world.resource_mut::<RenderGraph>();
+        let core_3d_graph = graph.get_sub_graph_mut(CORE_3D).unwrap();
+
+        core_3d_graph.add_node("voxel_attachments", attachments);
+        core_3d_graph.add_node("voxel_rebuild", RebuildNode);
+        core_3d_graph.add_node("voxel_physics", PhysicsNode);
+        core_3d_graph.add_node("voxel_trace", trace);
+        core_3d_graph.add_node("voxel_denoise", denoise);
+        core_3d_graph.add_node("voxel_temporal_upscale", temporal_upscale);
+        core_3d_graph.add_node("voxel_post_process", post_process);
+
+        core_3d_graph.add_node_edge("voxel_rebuild", "voxel_physics");
+        core_3d_graph.add_node_edge("voxel_physics", "voxel_trace");
+        core_3d_graph.add_node_edge(core_3d::graph::node::START_MAIN_PASS, "voxel_trace");
+        core_3d_graph.add_node_edge("voxel_trace", "voxel_denoise");
+        core_3d_graph.add_node_edge("voxel_denoise", "voxel_temporal_upscale");
+        core_3d_graph.add_node_edge(
+            "voxel_temporal_upscale",
+            core_3d::graph::node::MAIN_OPAQUE_PASS,
+        );
+        core_3d_graph.add_node_edge(core_3d::graph::node::BLOOM, "voxel_post_process");
+        core_3d_graph.add_node_edge("voxel_post_process", core_3d::graph::node::TONEMAPPING);
+
+        for slot in [
+            "normal",
+            "position",
+            "last_normal",
+            "last_position",
+            "accumulation",
+        ] {
+            core_3d_graph.add_slot_edge("voxel_attachments", slot, "voxel_trace", slot);
+        }
+        core_3d_graph.add_slot_edge("voxel_attachments", "normal", "voxel_denoise", "normal");
+        core_3d_graph.add_slot_edge("voxel_attachments", "position", "voxel_denoise", "position");
+        for slot in [
+            "normal",
+            "position",
+            "last_normal",
+            "last_position",
+            "history",
+        ] {
+            core_3d_graph.add_slot_edge("voxel_attachments", slot, "voxel_temporal_upscale", slot);
+        }
+
+        // Main graph compute
 
         graph.add_node("clear", ClearNode);
         graph.add_node("automata", AutomataNode);
         graph.add_node("animation", AnimationNode);
+        graph.add_node("particles", ParticleNode);
+        graph.add_node("integrity", IntegrityNode);
+        graph.add_node("navigation", NavigationNode);
+        graph.add_node("light_volume", LightVolumeNode);
+        graph.add_node("mip", MipNode);
 
         graph.add_node_edge("clear", "automata");
         graph.add_node_edge("automata", "animation");
-        graph.add_node_edge("animation", CAMERA_DRIVER);
+        graph.add_node_edge("animation", "particles");
+        graph.add_node_edge("particles", "integrity");
+        graph.add_node_edge("integrity", "navigation");
+        graph.add_node_edge("navigation", "light_volume");
+        graph.add_node_edge("light_volume", "mip");
+        graph.add_node_edge("mip", CAMERA_DRIVER);
 
         // Insert the voxel graph into the main render graph
         graph.add_sub_graph("voxel", voxel_graph);
@@ -101,10 +222,20 @@ pub struct RenderGraphSettings {
     pub clear: bool,
     pub automata: bool,
     pub animation: bool,
+    pub particles: bool,
+    pub integrity: bool,
+    /// streams the collision voxels back to the `NavigationGrid`, off by
+    /// default as it reads back from the gpu every frame
+    pub navigation: bool,
+    /// spreads the light of emissive voxels through the world
+    pub light_volume: bool,
+    /// averages the world into the mip texture for level of detail tracing
+    pub mip: bool,
     pub voxelization: bool,
     pub rebuild: bool,
     pub physics: bool,
     pub trace: bool,
+    pub denoise: bool,
 }
 
 impl Default for RenderGraphSettings {
@@ -113,10 +244,16 @@ impl Default for RenderGraphSettings {
             clear: true,
             automata: true,
             animation: true,
+            particles: true,
+            integrity: true,
+            navigation: false,
+            light_volume: true,
+            mip: true,
             voxelization: true,
             rebuild: true,
             physics: true,
             trace: true,
+            denoise: true,
         }
     }
 }
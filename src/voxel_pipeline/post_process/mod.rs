@@ -0,0 +1,337 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        bloom::{BloomCompositeMode, BloomSettings},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        texture::TextureCache,
+        view::ViewTarget,
+        Render, RenderApp, RenderSet,
+    },
+};
+pub use node::{BloomNode, PostProcessNode};
+
+mod node;
+
+const POST_PROCESS_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(8897319235294920679);
+
+/// height in pixels of the largest bloom mip, independent of the resolution
+const BLOOM_SIZE: u32 = 512;
+
+pub struct PostProcessPlugin;
+
+impl Plugin for PostProcessPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            POST_PROCESS_SHADER_HANDLE,
+            "../shaders/post_process.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_plugins(ExtractComponentPlugin::<PostProcessSettings>::default());
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .init_resource::<PostProcessPipeline>()
+            .add_systems(
+                Render,
+                (
+                    prepare_uniforms.in_set(RenderSet::Prepare),
+                    prepare_bloom_textures.in_set(RenderSet::Prepare),
+                ),
+            );
+    }
+}
+
+/// Effects applied to the hdr image after bloom and before tonemapping. Bloom
+/// itself is set up with bevy's `BloomSettings` on the same camera. Only voxel
+/// cameras, the ones with `TraceSettings`, use it, and only in hdr.
+#[derive(Component, Clone, Default, ExtractComponent)]
+pub struct PostProcessSettings {
+    /// brightens or darkens the image in stops, every stop doubles the light
+    pub exposure: f32,
+    /// how far apart the red and blue channels are pulled at the edges of the
+    /// screen, as a fraction of the screen. 0.005 is subtle
+    pub chromatic_aberration: f32,
+    /// how much the corners of the screen are darkened, from 0 to 1
+    pub vignette: f32,
+}
+
+#[derive(Clone, Copy, ShaderType)]
+struct PostProcessUniforms {
+    /// soft threshold for the first bloom downsample
+    threshold: Vec4,
+    /// uv rectangle of the camera viewport inside the target
+    viewport: Vec4,
+    exposure: f32,
+    chromatic_aberration: f32,
+    vignette: f32,
+}
+
+#[derive(Component)]
+struct ViewPostProcessUniformOffset(u32);
+
+/// Mip chain the bloom is blurred through, one view for each mip
+#[derive(Component)]
+struct ViewBloomTexture(Vec<TextureView>);
+
+fn prepare_uniforms(
+    mut commands: Commands,
+    query: Query<(
+        Entity,
+        &ExtractedCamera,
+        Option<&BloomSettings>,
+        Option<&PostProcessSettings>,
+    )>,
+    mut post_process_pipeline: ResMut<PostProcessPipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    post_process_pipeline.uniforms.clear();
+
+    for (entity, camera, bloom_settings, post_process_settings) in query.iter() {
+        if bloom_settings.is_none() && post_process_settings.is_none() {
+            continue;
+        }
+
+        // precomputed like bevy's own bloom
+        let threshold = bloom_settings.map_or(Vec4::ZERO, |bloom_settings| {
+            let prefilter = &bloom_settings.prefilter_settings;
+            let knee = prefilter.threshold * prefilter.threshold_softness.clamp(0.0, 1.0);
+            Vec4::new(
+                prefilter.threshold,
+                prefilter.threshold - knee,
+                2.0 * knee,
+                0.25 / (knee + 0.00001),
+            )
+        });
+        let post_process_settings = post_process_settings.cloned().unwrap_or_default();
+
+        let viewport = match (&camera.viewport, camera.physical_target_size) {
+            (Some(viewport), Some(target_size)) => {
+                let target_size = target_size.as_vec2();
+                (
+                    viewport.physical_position.as_vec2() / target_size,
+                    viewport.physical_size.as_vec2() / target_size,
+                )
+            }
+            _ => (Vec2::ZERO, Vec2::ONE),
+        };
+
+        let offset = post_process_pipeline.uniforms.push(PostProcessUniforms {
+            threshold,
+            viewport: Vec4::new(viewport.0.x, viewport.0.y, viewport.1.x, viewport.1.y),
+            exposure: post_process_settings.exposure,
+            chromatic_aberration: post_process_settings.chromatic_aberration,
+            vignette: post_process_settings.vignette,
+        });
+
+        commands
+            .entity(entity)
+            .insert(ViewPostProcessUniformOffset(offset));
+    }
+
+    post_process_pipeline
+        .uniforms
+        .write_buffer(&render_device, &render_queue);
+}
+
+fn prepare_bloom_textures(
+    mut commands: Commands,
+    mut texture_cache: ResMut<TextureCache>,
+    render_device: Res<RenderDevice>,
+    query: Query<(Entity, &ExtractedCamera), With<BloomSettings>>,
+) {
+    for (entity, camera) in query.iter() {
+        // cameras in the core 3d graph use bevy's own bloom
+        if camera.render_graph != "voxel" {
+            continue;
+        }
+        let Some(size) = camera.physical_viewport_size else {
+            continue;
+        };
+
+        let mip_level_count = BLOOM_SIZE.ilog2() - 1;
+        let width = size.x as f32 * BLOOM_SIZE as f32 / size.y.max(1) as f32;
+        let texture = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("bloom texture"),
+                size: Extent3d {
+                    width: (width as u32).max(1),
+                    height: BLOOM_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: ViewTarget::TEXTURE_FORMAT_HDR,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        let mip_views = (0..mip_level_count)
+            .map(|mip| {
+                texture.texture.create_view(&TextureViewDescriptor {
+                    label: Some("bloom mip view"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..default()
+                })
+            })
+            .collect();
+
+        commands.entity(entity).insert(ViewBloomTexture(mip_views));
+    }
+}
+
+/// How much of a bloom mip is blended into the one above it, the same curve as
+/// bevy's own bloom
+fn bloom_blend_factor(bloom_settings: &BloomSettings, mip: f32, max_mip: f32) -> f32 {
+    let mut low_frequency_boost = (1.0
+        - (1.0 - (mip / max_mip)).powf(1.0 / (1.0 - bloom_settings.low_frequency_boost_curvature)))
+        * bloom_settings.low_frequency_boost;
+    let high_pass = 1.0
+        - (((mip / max_mip) - bloom_settings.high_pass_frequency)
+            / bloom_settings.high_pass_frequency)
+            .clamp(0.0, 1.0);
+
+    if bloom_settings.composite_mode == BloomCompositeMode::EnergyConserving {
+        low_frequency_boost *= 1.0 - bloom_settings.intensity;
+    }
+
+    (bloom_settings.intensity + low_frequency_boost) * high_pass
+}
+
+#[derive(Resource)]
+struct PostProcessPipeline {
+    bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
+    downsample_first_pipeline_id: CachedRenderPipelineId,
+    downsample_pipeline_id: CachedRenderPipelineId,
+    upsample_pipeline_id: CachedRenderPipelineId,
+    upsample_additive_pipeline_id: CachedRenderPipelineId,
+    post_process_pipeline_id: CachedRenderPipelineId,
+    uniforms: DynamicUniformBuffer<PostProcessUniforms>,
+}
+
+impl FromWorld for PostProcessPipeline {
+    fn from_world(render_world: &mut World) -> Self {
+        let render_device = render_world.resource::<RenderDevice>();
+
+        let bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("post process bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: Some(PostProcessUniforms::min_size()),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("post process sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        // the upsamples blend into the mip above with a constant set per pass
+        let upsample_blend = |dst_factor| BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::Constant,
+                dst_factor,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: BlendFactor::Zero,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        };
+
+        let queue_pipeline = |label: &'static str, entry_point: &'static str, blend| {
+            render_world
+                .resource::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some(label.into()),
+                    layout: vec![bind_group_layout.clone()],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader: POST_PROCESS_SHADER_HANDLE,
+                        shader_defs: vec![],
+                        entry_point: entry_point.into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: ViewTarget::TEXTURE_FORMAT_HDR,
+                            blend,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                })
+        };
+
+        let downsample_first_pipeline_id =
+            queue_pipeline("bloom downsample first pipeline", "downsample_first", None);
+        let downsample_pipeline_id =
+            queue_pipeline("bloom downsample pipeline", "downsample", None);
+        let upsample_pipeline_id = queue_pipeline(
+            "bloom upsample pipeline",
+            "upsample",
+            Some(upsample_blend(BlendFactor::OneMinusConstant)),
+        );
+        let upsample_additive_pipeline_id = queue_pipeline(
+            "bloom additive upsample pipeline",
+            "upsample",
+            Some(upsample_blend(BlendFactor::One)),
+        );
+        let post_process_pipeline_id =
+            queue_pipeline("post process pipeline", "post_process", None);
+
+        PostProcessPipeline {
+            bind_group_layout,
+            sampler,
+            downsample_first_pipeline_id,
+            downsample_pipeline_id,
+            upsample_pipeline_id,
+            upsample_additive_pipeline_id,
+            post_process_pipeline_id,
+            uniforms: DynamicUniformBuffer::default(),
+        }
+    }
+}
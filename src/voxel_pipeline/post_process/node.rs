@@ -0,0 +1,274 @@
+use super::{
+    super::trace::{copy_outside_viewport, set_viewport, TraceSettings},
+    bloom_blend_factor, PostProcessPipeline, PostProcessSettings, ViewBloomTexture,
+    ViewPostProcessUniformOffset,
+};
+use bevy::{
+    core_pipeline::bloom::{BloomCompositeMode, BloomSettings},
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::RenderContext,
+        view::{ExtractedView, ViewTarget},
+    },
+};
+
+/// Bloom for the voxel graph, bevy's own bloom node can't be added to other graphs
+pub struct BloomNode {
+    query: QueryState<
+        (
+            &'static ExtractedCamera,
+            &'static ViewTarget,
+            &'static BloomSettings,
+            &'static ViewBloomTexture,
+            &'static ViewPostProcessUniformOffset,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl BloomNode {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: world.query_filtered(),
+        }
+    }
+}
+
+impl render_graph::Node for BloomNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.view_entity();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let post_process_pipeline = world.resource::<PostProcessPipeline>();
+
+        let (camera, target, bloom_settings, bloom_texture, uniform_offset) =
+            match self.query.get_manual(world, view_entity) {
+                Ok(result) => result,
+                Err(_) => return Ok(()),
+            };
+
+        if bloom_settings.intensity == 0.0 {
+            return Ok(());
+        }
+
+        let upsample_pipeline_id = match bloom_settings.composite_mode {
+            BloomCompositeMode::EnergyConserving => post_process_pipeline.upsample_pipeline_id,
+            BloomCompositeMode::Additive => post_process_pipeline.upsample_additive_pipeline_id,
+        };
+        let (downsample_first_pipeline, downsample_pipeline, upsample_pipeline) = match (
+            pipeline_cache.get_render_pipeline(post_process_pipeline.downsample_first_pipeline_id),
+            pipeline_cache.get_render_pipeline(post_process_pipeline.downsample_pipeline_id),
+            pipeline_cache.get_render_pipeline(upsample_pipeline_id),
+        ) {
+            (Some(downsample_first), Some(downsample), Some(upsample)) => {
+                (downsample_first, downsample, upsample)
+            }
+            _ => return Ok(()),
+        };
+
+        let mips = &bloom_texture.0;
+        let max_mip = (mips.len() - 1) as f32;
+
+        // downsample the image through the mips, then blend them back up into
+        // the image from the smallest mip
+        let mut passes = vec![(
+            "bloom downsample first pass",
+            downsample_first_pipeline,
+            target.main_texture_view(),
+            &mips[0],
+            None,
+        )];
+        for mip in 1..mips.len() {
+            passes.push((
+                "bloom downsample pass",
+                downsample_pipeline,
+                &mips[mip - 1],
+                &mips[mip],
+                None,
+            ));
+        }
+        for mip in (1..mips.len()).rev() {
+            passes.push((
+                "bloom upsample pass",
+                upsample_pipeline,
+                &mips[mip],
+                &mips[mip - 1],
+                Some(bloom_blend_factor(bloom_settings, mip as f32, max_mip)),
+            ));
+        }
+        passes.push((
+            "bloom final upsample pass",
+            upsample_pipeline,
+            &mips[0],
+            target.main_texture_view(),
+            Some(bloom_blend_factor(bloom_settings, 0.0, max_mip)),
+        ));
+
+        for (label, pipeline, source, destination, blend_factor) in passes {
+            // only the final pass draws into the target
+            let viewport = if destination.id() == target.main_texture_view().id() {
+                camera.viewport.clone()
+            } else {
+                None
+            };
+
+            let bind_group = render_context.render_device().create_bind_group(
+                None,
+                &post_process_pipeline.bind_group_layout,
+                &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&post_process_pipeline.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: post_process_pipeline.uniforms.binding().unwrap(),
+                    },
+                ],
+            );
+
+            let mut render_pass =
+                render_context
+                    .command_encoder()
+                    .begin_render_pass(&RenderPassDescriptor {
+                        label: Some(label),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: destination,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Load,
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[uniform_offset.0]);
+            if let Some(blend_factor) = blend_factor {
+                render_pass.set_blend_constant(
+                    Color::rgb_linear(blend_factor, blend_factor, blend_factor).into(),
+                );
+            }
+            set_viewport(&mut render_pass, &viewport);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Exposure, vignette and chromatic aberration from `PostProcessSettings`
+pub struct PostProcessNode {
+    query: QueryState<
+        (
+            &'static ExtractedCamera,
+            &'static ViewTarget,
+            &'static ViewPostProcessUniformOffset,
+            &'static PostProcessSettings,
+        ),
+        With<TraceSettings>,
+    >,
+}
+
+impl PostProcessNode {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: world.query_filtered(),
+        }
+    }
+}
+
+impl render_graph::Node for PostProcessNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.view_entity();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let post_process_pipeline = world.resource::<PostProcessPipeline>();
+
+        let (camera, target, uniform_offset, _) = match self.query.get_manual(world, view_entity) {
+            Ok(result) => result,
+            Err(_) => return Ok(()),
+        };
+
+        // the pipeline writes the hdr format
+        if !target.is_hdr() {
+            return Ok(());
+        }
+
+        let pipeline = match pipeline_cache
+            .get_render_pipeline(post_process_pipeline.post_process_pipeline_id)
+        {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+
+        let post_process = target.post_process_write();
+        copy_outside_viewport(render_context, world, camera, &post_process);
+
+        let bind_group = render_context.render_device().create_bind_group(
+            None,
+            &post_process_pipeline.bind_group_layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(post_process.source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&post_process_pipeline.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: post_process_pipeline.uniforms.binding().unwrap(),
+                },
+            ],
+        );
+
+        let mut render_pass =
+            render_context
+                .command_encoder()
+                .begin_render_pass(&RenderPassDescriptor {
+                    label: Some("post process pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: post_process.destination,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[uniform_offset.0]);
+        set_viewport(&mut render_pass, &camera.viewport);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-#import bevy_core_pipeline::fullscreen_vertex_shader
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 
 struct Uniforms {
     offsets: array<vec4<f32>, 25>,
@@ -15,10 +15,8 @@ struct PassData {
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 @group(0) @binding(1)
-var accumulation_attachment: texture_storage_2d<rgba16float, read_write>;
-@group(0) @binding(2)
 var normal_attachment: texture_storage_2d<rgba16float, read_write>;
-@group(0) @binding(3)
+@group(0) @binding(2)
 var position_attachment: texture_storage_2d<rgba32float, read_write>;
 @group(1) @binding(0)
 var<uniform> pass_data: PassData;
@@ -27,8 +25,12 @@ var colour_attachment: texture_2d<f32>;
 
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
-    let sample_pos = vec2<i32>(in.position.xy);
-    let colour = textureLoad(colour_attachment, sample_pos, 0).rgb;
+    // the attachments start at the top left of the camera viewport and the
+    // colour is only filled up to the size the world is traced at
+    let sample_pos = vec2<i32>(in.uv * vec2<f32>(textureDimensions(normal_attachment)));
+    let max_sample_pos = vec2<i32>(textureDimensions(normal_attachment)) - 1;
+    let viewport_offset = vec2<i32>(in.position.xy) - sample_pos;
+    let colour = textureLoad(colour_attachment, sample_pos + viewport_offset, 0).rgb;
     let normal = textureLoad(normal_attachment, sample_pos).rgb;
     let position = textureLoad(position_attachment, sample_pos).rgb;
     var output_colour = colour;
@@ -41,9 +43,9 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     let p_phi = pass_data.position_phi;
 
     for (var i = 0; i < 25; i += 1) {
-        let new_sample_pos = sample_pos + vec2<i32>(denoise_strength * uniforms.offsets[i].xy);
+        let new_sample_pos = clamp(sample_pos + vec2<i32>(denoise_strength * uniforms.offsets[i].xy), vec2(0), max_sample_pos);
 
-        let new_colour = textureLoad(colour_attachment, new_sample_pos, 0).rgb;
+        let new_colour = textureLoad(colour_attachment, new_sample_pos + viewport_offset, 0).rgb;
         let diff = colour - new_colour;
         let dist2 = dot(diff, diff);
         let colour_weight = min(exp(-dist2 / c_phi), 1.0);
@@ -65,10 +67,6 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     }
 
     output_colour = sum / sum_w;
-    // output_colour = position;
-    // output_colour = sum;
-    // output_colour = abs(vec3(uniforms.offsets[5 * i32(in.uv.x * 5.0) + i32(in.uv.y * 5.0)].xyz));
 
-    textureStore(accumulation_attachment, sample_pos, vec4(sum / sum_w, 0.0));
     return vec4<f32>(output_colour, 1.0);
 }
@@ -0,0 +1,96 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct PostProcessUniforms {
+    threshold: vec4<f32>,
+    // uv rectangle of the camera viewport inside the target
+    viewport: vec4<f32>,
+    exposure: f32,
+    chromatic_aberration: f32,
+    vignette: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> uniforms: PostProcessUniforms;
+
+fn sample_source(uv: vec2<f32>, offset: vec2<f32>) -> vec3<f32> {
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(source));
+    return textureSample(source, source_sampler, uv + offset * texel_size).rgb;
+}
+
+// only lets the light above the threshold through, with a soft knee
+fn soft_threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var softness = clamp(brightness - uniforms.threshold.y, 0.0, uniforms.threshold.z);
+    softness = softness * softness * uniforms.threshold.w;
+    let contribution = max(brightness - uniforms.threshold.x, softness) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+// 13 tap downsample from call of duty: advanced warfare
+fn downsample_13(uv: vec2<f32>) -> vec3<f32> {
+    let a = sample_source(uv, vec2(-2.0, 2.0));
+    let b = sample_source(uv, vec2(0.0, 2.0));
+    let c = sample_source(uv, vec2(2.0, 2.0));
+    let d = sample_source(uv, vec2(-2.0, 0.0));
+    let e = sample_source(uv, vec2(0.0, 0.0));
+    let f = sample_source(uv, vec2(2.0, 0.0));
+    let g = sample_source(uv, vec2(-2.0, -2.0));
+    let h = sample_source(uv, vec2(0.0, -2.0));
+    let i = sample_source(uv, vec2(2.0, -2.0));
+    let j = sample_source(uv, vec2(-1.0, 1.0));
+    let k = sample_source(uv, vec2(1.0, 1.0));
+    let l = sample_source(uv, vec2(-1.0, -1.0));
+    let m = sample_source(uv, vec2(1.0, -1.0));
+
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// the view target is shared by every camera drawing to it
+fn viewport_uv(uv: vec2<f32>) -> vec2<f32> {
+    return uniforms.viewport.xy + uv * uniforms.viewport.zw;
+}
+
+@fragment
+fn downsample_first(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return vec4(soft_threshold(downsample_13(viewport_uv(in.uv))), 1.0);
+}
+
+@fragment
+fn downsample(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample_13(in.uv), 1.0);
+}
+
+// 3x3 tent filter
+@fragment
+fn upsample(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    var color = sample_source(in.uv, vec2(0.0, 0.0)) * 4.0;
+    color += (sample_source(in.uv, vec2(-1.0, 0.0)) + sample_source(in.uv, vec2(1.0, 0.0))) * 2.0;
+    color += (sample_source(in.uv, vec2(0.0, -1.0)) + sample_source(in.uv, vec2(0.0, 1.0))) * 2.0;
+    color += sample_source(in.uv, vec2(-1.0, -1.0)) + sample_source(in.uv, vec2(1.0, -1.0));
+    color += sample_source(in.uv, vec2(-1.0, 1.0)) + sample_source(in.uv, vec2(1.0, 1.0));
+    return vec4(color / 16.0, 1.0);
+}
+
+@fragment
+fn post_process(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let centered = in.uv - 0.5;
+
+    // red and blue are pulled apart more towards the edges
+    let uv = viewport_uv(in.uv);
+    let offset = centered * 2.0 * uniforms.chromatic_aberration * uniforms.viewport.zw;
+    let center = textureSample(source, source_sampler, uv);
+    var color = vec3(
+        textureSample(source, source_sampler, uv + offset).r,
+        center.g,
+        textureSample(source, source_sampler, uv - offset).b,
+    );
+
+    color *= 1.0 - uniforms.vignette * smoothstep(0.25, 0.75, length(centered));
+    color *= exp2(uniforms.exposure);
+
+    return vec4(color, center.a);
+}
@@ -80,6 +80,6 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
 
     write_pos(vec3<i32>(texture_pos), material, voxelization_uniforms.flags);
 
-    let color = voxel_uniforms.materials[material].rgb;
+    let color = voxel_uniforms.pallete[material].rgb;
     return vec4<f32>(color, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_voxel_engine::common::{TraceUniforms, clip_aabb, same_surface}
+
+@group(0) @binding(0)
+var<uniform> trace_uniforms: TraceUniforms;
+@group(0) @binding(1)
+var accumulation: texture_storage_2d<rgba16float, read_write>;
+@group(0) @binding(2)
+var normal: texture_storage_2d<rgba16float, read_write>;
+@group(0) @binding(3)
+var position: texture_storage_2d<rgba32float, read_write>;
+@group(0) @binding(4)
+var last_normal: texture_storage_2d<rgba16float, read_write>;
+@group(0) @binding(5)
+var last_position: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(0)
+var color: texture_2d<f32>;
+
+// Temporal reprojection of the traced frame. Blends it with the accumulated
+// frames from the same spot on the surface last frame, the alpha channel
+// counts how many frames are in it. `store` keeps the result for the next frame.
+@fragment
+fn reproject(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let resolution = vec2<f32>(textureDimensions(normal));
+    // the attachments start at the top left of the camera viewport, the
+    // colour is the whole target
+    let pixel = vec2<i32>(in.uv * resolution);
+    let viewport_offset = vec2<i32>(in.position.xy) - pixel;
+    let current_color = textureLoad(color, pixel + viewport_offset, 0).rgb;
+    let current_normal = textureLoad(normal, pixel).xyz;
+    let current_position = textureLoad(position, pixel).xyz;
+
+    let last_clip_oweigh = trace_uniforms.last_camera * vec4(current_position, 1.0);
+    let last_clip = vec2(1.0, -1.0) * last_clip_oweigh.xy / last_clip_oweigh.w;
+    if last_clip_oweigh.w <= 0.0 || any(last_clip <= vec2(-1.0)) || any(last_clip >= vec2(1.0)) {
+        return vec4(current_color, 1.0);
+    }
+
+    // only reuse the history if it was on the same surface last frame
+    let last_pixel = vec2<i32>((last_clip * 0.5 + 0.5) * resolution);
+    let history_normal = textureLoad(last_normal, last_pixel).xyz;
+    let history_position = textureLoad(last_position, last_pixel).xyz;
+    if !same_surface(current_normal, current_position, history_normal, history_position) {
+        return vec4(current_color, 1.0);
+    }
+
+    // the history is kept inside the colours around the pixel, half from the
+    // plus shaped and half from the square neighbourhood
+    var min_plus = current_color;
+    var max_plus = current_color;
+    var min_square = current_color;
+    var max_square = current_color;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = clamp(pixel + vec2(x, y), vec2(0), vec2<i32>(resolution) - 1);
+            let neighbor_color = textureLoad(color, neighbor + viewport_offset, 0).rgb;
+            min_square = min(min_square, neighbor_color);
+            max_square = max(max_square, neighbor_color);
+            if x == 0 || y == 0 {
+                min_plus = min(min_plus, neighbor_color);
+                max_plus = max(max_plus, neighbor_color);
+            }
+        }
+    }
+
+    let history = textureLoad(accumulation, last_pixel);
+    let frames = min(history.a + 1.0, f32(trace_uniforms.accumulation_frames));
+    let history_color = clip_aabb(history.rgb, (min_plus + min_square) / 2.0, (max_plus + max_square) / 2.0);
+    let blended = mix(history_color, current_color, 1.0 / frames);
+
+    return vec4(blended, frames);
+}
+
+// Keeps the blended frame around for the next frame
+@fragment
+fn store(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.uv * vec2<f32>(textureDimensions(accumulation)));
+    let blended = textureLoad(color, vec2<i32>(in.position.xy), 0);
+    textureStore(accumulation, pixel, blended);
+    return vec4(blended.rgb, 1.0);
+}
@@ -1,10 +1,24 @@
 #define_import_path bevy_voxel_engine::bindings
 
-#import bevy_voxel_engine::common::VoxelUniforms
+#import bevy_voxel_engine::common::{
+    VoxelUniforms,
+    Portal,
+}
 
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
 var voxel_world: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
-var<storage, read_write> gh: array<u32>;
\ No newline at end of file
+var<storage, read_write> gh: array<u32>;
+// averaged colours of the world, the alpha is how much of a cell is filled
+@group(0) @binding(3)
+var mip_texture: texture_3d<f32>;
+@group(0) @binding(4)
+var texture_sampler: sampler;
+// indexed by the material of the portal voxels
+@group(0) @binding(5)
+var<storage, read> voxel_portals: array<Portal>;
+// the most common material and flags of every mip cell
+@group(0) @binding(6)
+var mip_material: texture_3d<u32>;
@@ -5,6 +5,13 @@ const PORTAL_FLAG = 64u; // 0b01000000
 const ANIMATION_FLAG = 32u; // 0b00100000
 const COLLISION_FLAG = 16u; // 0b00010000
 const SAND_FLAG = 8u; // 0b00001000
+const ANCHOR_FLAG = 4u; // 0b00000100
+
+// ray flags above the voxel flags for shoot_ray
+const TRANSPARENT_RAY = 256u; // passes through transparent voxels
+const REFRACT_RAY = 512u; // bends when passing through transparent voxels
+const PORTAL_LIMIT_RAY = 1024u; // stops at a portal after going through flags >> PORTAL_DEPTH_SHIFT of them
+const PORTAL_DEPTH_SHIFT = 16u;
 
 const VOXELS_PER_METER: f32 = 4.0;
 
@@ -16,9 +23,45 @@ struct Portal {
     normal: vec3<f32>,
 }
 
+struct Particle {
+    position: vec3<f32>,
+    life: f32,
+    velocity: vec3<f32>,
+    voxel: u32,
+    gravity: vec3<f32>,
+    settle: u32,
+    collision_effect: vec3<f32>,
+}
+
+struct Particles {
+    next: atomic<u32>,
+    particles: array<Particle>,
+}
+
+// texture space centers of the voxels destroyed since the last integrity pass
+struct Edits {
+    count: atomic<u32>,
+    // the next edit the integrity pass handles
+    cursor: atomic<u32>,
+    positions: array<vec3<i32>>,
+}
+
+struct VoxelMaterial {
+    roughness: f32,
+    metallic: f32,
+    emission: f32,
+    transparency: f32,
+    ior: f32,
+    // ior takes up 16 bytes on the rust side to keep the array stride a
+    // multiple of 16
+    padding_a: f32,
+    padding_b: f32,
+    padding_c: f32,
+}
+
 struct VoxelUniforms {
-    materials: array<vec4<f32>, 256>,
-    portals: array<Portal, 32>,
+    pallete: array<vec4<f32>, 256>,
+    materials: array<VoxelMaterial, 256>,
     levels: array<vec4<u32>, 8>,
     offsets: array<vec4<u32>, 8>,
     texture_size: u32,
@@ -29,10 +72,36 @@ struct TraceUniforms {
     camera_inverse: mat4x4<f32>,
     last_camera: mat4x4<f32>,
     projection: mat4x4<f32>,
+    // the eye the primary rays come from, or their direction when w is 0
+    ray_center: vec4<f32>,
     time: f32,
     show_ray_steps: u32,
     samples: u32,
     shadows: u32,
+    indirect_bounces: u32,
+    accumulation_frames: u32,
+    sun_dir: vec3<f32>,
+    ambient: f32,
+    sun_color: vec3<f32>,
+    sky_model: u32,
+    sky_color: vec3<f32>,
+    refraction: u32,
+    reflection_bounces: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    fog_height: f32,
+    fog_falloff: f32,
+    fog_distance: f32,
+    fog_samples: u32,
+    camera_position: vec3<f32>,
+    shadow_cone: f32,
+    shadow_distance: f32,
+    jitter: vec2<f32>,
+    lod_bias: f32,
+    cone_tracing: u32,
+    cone_distance: f32,
+    max_portal_depth: u32,
+    portal_fallback: vec3<f32>,
 };
 
 fn get_clip_space(frag_pos: vec4<f32>, dimensions: vec2<f32>) -> vec2<f32> {
@@ -72,6 +141,20 @@ fn cosine_hemisphere(n: vec3<f32>, seed: vec3<u32>) -> vec3<f32> {
     return normalize(r * sin(theta) * b + sqrt(1.0 - u.x) * n + r * cos(theta) * t);
 }
 
+// uniformly distributed direction at most acos(cos_max) away from dir
+fn sample_cone(dir: vec3<f32>, cos_max: f32, seed: vec3<u32>) -> vec3<f32> {
+    let u = hash(seed);
+
+    let cos_theta = mix(1.0, cos_max, u.x);
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let phi = 2.0 * PI * u.y;
+
+    let b = normalize(cross(dir, vec3<f32>(0.0, 1.0, 1.0)));
+    let t = cross(b, dir);
+
+    return normalize(sin_theta * cos(phi) * b + sin_theta * sin(phi) * t + cos_theta * dir);
+}
+
 struct Ray {
     pos: vec3<f32>,
     dir: vec3<f32>,
@@ -111,6 +194,11 @@ fn in_bounds(v: vec3<f32>) -> bool {
     return (s.x * s.y * s.z) > 0.5;
 }
 
+// whether the history of the last frame was on the same surface as the pixel
+fn same_surface(normal: vec3<f32>, position: vec3<f32>, history_normal: vec3<f32>, history_position: vec3<f32>) -> bool {
+    return dot(normal, history_normal) >= 0.9 && abs(dot(history_position - position, normal)) <= 0.05;
+}
+
 fn clip_aabb(hist: vec3<f32>, min_aabb: vec3<f32>, max_aabb: vec3<f32>) -> vec3<f32> {
     let p_clip = 0.5 * (max_aabb + min_aabb);
     let e_clip = 0.5 * (max_aabb - min_aabb);
@@ -134,6 +222,15 @@ struct SkyboxInfo {
 };
 
 fn skybox(dir: vec3<f32>, time_of_day: f32) -> SkyboxInfo {
+    let t = ((time_of_day + 4.0) * ((360.0 / 24.0) * PI / 180.0));
+    let sun_pos = normalize(vec3<f32>(0.0, -(sin(t)), cos(t)));
+    let sun_dir = normalize(-sun_pos);
+
+    return SkyboxInfo(sun_pos, sun_dir, procedural_sky(dir, sun_pos));
+}
+
+// sun_pos is the direction towards the sun
+fn procedural_sky(dir: vec3<f32>, sun_pos: vec3<f32>) -> vec3<f32> {
     var col = vec3<f32>(0.0, 0.0, 0.0);
 
     var sunset_dark: array<vec3<f32>, 4u> = array<vec3<f32>, 4u>(
@@ -155,9 +252,6 @@ fn skybox(dir: vec3<f32>, time_of_day: f32) -> SkyboxInfo {
         vec3<f32>(-0.000000000000000483417267228435, 0.13545893132686615, -0.0000000000000014694301099188)
     );
 
-    let t = ((time_of_day + 4.0) * ((360.0 / 24.0) * PI / 180.0));
-    let sun_pos = normalize(vec3<f32>(0.0, -(sin(t)), cos(t)));
-
     {
         let brightness: f32 = ((1.5 * smoothstep((80.0 * PI / 180.0), 0.0, acos(dot(dir, sun_pos)))) - 0.5);
         let sunset = array<vec3<f32>, 4u>(
@@ -189,11 +283,7 @@ fn skybox(dir: vec3<f32>, time_of_day: f32) -> SkyboxInfo {
         0.949999988079071
     )) / vec3<f32>(acos(dot(sun_pos, dir))));
     
-    col = max((col + (0.5 * sun_col)), sun_col);
-
-    let sun_dir = normalize(-sun_pos);
-
-    return SkyboxInfo(sun_pos, sun_dir, col);
+    return max((col + (0.5 * sun_col)), sun_col);
 }
 
 fn rotate_axis(p: vec3<f32>, axis: vec3<f32>, angle: f32) -> vec3<f32> {
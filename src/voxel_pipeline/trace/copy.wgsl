@@ -0,0 +1,10 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+@group(0) @binding(0)
+var color: texture_2d<f32>;
+
+// Carries the image over to the other texture of the view target as it is
+@fragment
+fn copy(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return textureLoad(color, vec2<i32>(in.position.xy), 0);
+}
@@ -1,20 +1,30 @@
-use super::voxel_world::VoxelData;
+use super::{denoise::DenoiseSettings, voxel_world::VoxelData, RenderGraphSettings};
 use bevy::{
     asset::{embedded_asset, load_internal_asset},
-    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    core::FrameCount,
+    core_pipeline::{
+        clear_color::ClearColorConfig,
+        core_3d::{Camera3dDepthLoadOp, CORE_3D_DEPTH_FORMAT},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::query::Has,
     prelude::*,
     render::{
+        camera::{ExtractedCamera, Viewport},
         extract_component::{ExtractComponent, ExtractComponentPlugin},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
         render_resource::*,
-        renderer::{RenderDevice, RenderQueue},
-        view::{ExtractedView, ViewTarget},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        view::{ExtractedView, PostProcessWrite, ViewTarget},
         Render, RenderApp, RenderSet,
     },
     utils::HashMap,
 };
 pub use node::TraceNode;
+pub use upscale::TemporalUpscaleNode;
 
 mod node;
+mod upscale;
 
 const COMMON_HANDLE: Handle<Shader> = Handle::weak_from_u128(1874948457211004189);
 const BINDINGS_HANDLE: Handle<Shader> = Handle::weak_from_u128(1874948457211004188);
@@ -27,17 +37,27 @@ impl Plugin for TracePlugin {
         #[cfg(any(not(target_family = "windows"), target_env = "gnu"))]
         {
             embedded_asset!(app, "src/", "trace.wgsl");
+            embedded_asset!(app, "src/", "accumulation.wgsl");
+            embedded_asset!(app, "src/", "upscale.wgsl");
+            embedded_asset!(app, "src/", "copy.wgsl");
         }
         #[cfg(all(target_family = "windows", not(target_env = "gnu")))]
         {
             embedded_asset!(app, "src\\", "trace.wgsl");
+            embedded_asset!(app, "src\\", "accumulation.wgsl");
+            embedded_asset!(app, "src\\", "upscale.wgsl");
+            embedded_asset!(app, "src\\", "copy.wgsl");
         }
 
         load_internal_asset!(app, COMMON_HANDLE, "common.wgsl", Shader::from_wgsl);
         load_internal_asset!(app, BINDINGS_HANDLE, "bindings.wgsl", Shader::from_wgsl);
         load_internal_asset!(app, RAYTRACING_HANDLE, "raytracing.wgsl", Shader::from_wgsl);
 
-        app.add_plugins(ExtractComponentPlugin::<TraceSettings>::default());
+        app.init_resource::<VoxelSky>()
+            .add_plugins(ExtractComponentPlugin::<TraceSettings>::default())
+            .add_plugins(ExtractComponentPlugin::<VoxelFog>::default())
+            .add_plugins(ExtractResourcePlugin::<VoxelSky>::default())
+            .add_systems(Update, setup_voxel_render);
     }
 
     fn finish(&self, app: &mut App) {
@@ -55,13 +75,89 @@ impl Plugin for TracePlugin {
 struct TracePipelineData {
     trace_pipeline_id: CachedRenderPipelineId,
     trace_bind_group_layout: BindGroupLayout,
+    reproject_pipeline_id: CachedRenderPipelineId,
+    store_pipeline_id: CachedRenderPipelineId,
+    upscale_pipeline_id: CachedRenderPipelineId,
+    history_store_pipeline_id: CachedRenderPipelineId,
+    copy_pipeline_id: CachedRenderPipelineId,
+    accumulation_bind_group_layout: BindGroupLayout,
+    color_bind_group_layout: BindGroupLayout,
+}
+
+/// Traces the voxel world for a regular `Camera3dBundle` as part of bevy's
+/// `core_3d` graph, so it gets bloom, taa, gizmos and meshes like any other
+/// camera. `TraceSettings` and `DenoiseSettings` are added if they are missing.
+#[derive(Component, Clone, Default)]
+pub struct VoxelRender;
+
+fn setup_voxel_render(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Camera, &mut Camera3d), Added<VoxelRender>>,
+    settings_query: Query<(Has<TraceSettings>, Has<DenoiseSettings>)>,
+) {
+    for (entity, mut camera, mut camera_3d) in query.iter_mut() {
+        // the trace renders in hdr and the main pass has to keep its colour and depth
+        camera.hdr = true;
+        camera_3d.clear_color = ClearColorConfig::None;
+        camera_3d.depth_load_op = Camera3dDepthLoadOp::Load;
+
+        let (has_trace_settings, has_denoise_settings) = settings_query.get(entity).unwrap();
+        if !has_trace_settings {
+            commands.entity(entity).insert(TraceSettings::default());
+        }
+        if !has_denoise_settings {
+            commands.entity(entity).insert(DenoiseSettings::default());
+        }
+    }
 }
 
 #[derive(Component, Clone, ExtractComponent)]
 pub struct TraceSettings {
     pub show_ray_steps: bool,
+    /// paths traced per pixel when `indirect_bounces` is not 0 and shadow rays
+    /// per pixel for `ShadowQuality::Soft`
     pub samples: u32,
-    pub shadows: bool,
+    pub shadows: ShadowQuality,
+    /// angle in radians from the center of the sun to its edge, bigger suns
+    /// give softer shadows
+    pub shadow_cone_angle: f32,
+    /// surfaces further than this from the camera in meters skip their shadow
+    /// rays, 0 has no limit
+    pub shadow_distance: f32,
+    /// how many times light bounces around the world, emissive voxels light
+    /// the world through these bounces. 0 uses cheap ambient occlusion, or
+    /// `cone_tracing`, and the light volume for emissive voxels instead
+    pub indirect_bounces: u32,
+    /// the most frames blended together when `indirect_bounces` is not 0 or
+    /// the shadows are soft, 0 or 1 turns the temporal accumulation off. The
+    /// reprojected history makes up at most `1 - 1 / accumulation_frames` of
+    /// the image, clamped to the colours around each pixel
+    pub accumulation_frames: u32,
+    /// bend rays by the ior of the transparent voxels they pass through
+    pub refraction: bool,
+    /// trace reflections off of glossy voxels instead of only reflecting the sky
+    pub reflections: bool,
+    /// the most reflections followed from a pixel, reflections of reflections
+    /// need more than 1
+    pub max_reflection_bounces: u32,
+    /// fraction of the resolution the world is traced at. Below 1 the rays are
+    /// jittered every frame and upscaled back to full resolution over time
+    pub render_scale: f32,
+    /// how many pixels a cell of the mip texture has to shrink to before rays
+    /// stop at it instead of tracing the voxels inside, 0 always traces the
+    /// full resolution world. Needs `RenderGraphSettings::mip`
+    pub lod_bias: f32,
+    /// when `indirect_bounces` is 0, traces a few wide cones through the mip
+    /// texture for large scale ambient occlusion and a bounce of light off of
+    /// the voxels nearby. Needs `RenderGraphSettings::mip`
+    pub cone_tracing: bool,
+    /// how far the cones look for voxels in meters
+    pub cone_distance: f32,
+    /// the most portals a ray from the camera goes through, portals seen
+    /// through more than this many others are filled with `portal_fallback`
+    pub max_portal_depth: u32,
+    /// colour of the portals past `max_portal_depth`
+    pub portal_fallback: Color,
 }
 
 impl Default for TraceSettings {
@@ -69,21 +165,174 @@ impl Default for TraceSettings {
         Self {
             show_ray_steps: false,
             samples: 1,
-            shadows: true,
+            shadows: ShadowQuality::Hard,
+            shadow_cone_angle: 0.02,
+            shadow_distance: 0.0,
+            indirect_bounces: 0,
+            accumulation_frames: 32,
+            refraction: true,
+            reflections: true,
+            max_reflection_bounces: 2,
+            render_scale: 1.0,
+            lod_bias: 1.0,
+            cone_tracing: false,
+            cone_distance: 8.0,
+            max_portal_depth: 8,
+            portal_fallback: Color::BLACK,
+        }
+    }
+}
+
+impl TraceSettings {
+    /// whether the trace blends its result with the last frames
+    pub fn accumulates(&self) -> bool {
+        self.accumulation_frames > 1
+            && (self.indirect_bounces != 0 || self.shadows == ShadowQuality::Soft)
+    }
+
+    /// whether the trace runs at a lower resolution and is upscaled temporally
+    pub fn upscales(&self) -> bool {
+        self.render_scale < 1.0
+    }
+
+    /// the size in pixels the world is traced at for a viewport of `size`
+    pub fn render_size(&self, size: UVec2) -> UVec2 {
+        (size.as_vec2() * self.render_scale.clamp(0.0, 1.0))
+            .ceil()
+            .as_uvec2()
+            .max(UVec2::ONE)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowQuality {
+    Off,
+    /// a single shadow ray towards the center of the sun
+    Hard,
+    /// `TraceSettings::samples` shadow rays spread over the sun, accumulated
+    /// over several frames
+    Soft,
+}
+
+/// The sun and the sky around the voxel world, shared by every camera
+#[derive(Resource, Clone, ExtractResource)]
+pub struct VoxelSky {
+    pub sun: SunPosition,
+    pub sun_color: Color,
+    pub sun_intensity: f32,
+    pub model: SkyModel,
+    /// how much the sky lights up the world
+    pub ambient: f32,
+}
+
+impl Default for VoxelSky {
+    fn default() -> Self {
+        Self {
+            sun: SunPosition::Direction(Vec3::new(0.4, -0.6, 0.8)),
+            sun_color: Color::WHITE,
+            sun_intensity: 1.0,
+            model: SkyModel::Solid(Color::rgb_linear(0.3, 0.3, 0.3)),
+            ambient: 1.0,
+        }
+    }
+}
+
+/// Height fog seen by the camera it is on, lit by the sky and the sun
+#[derive(Component, Clone, ExtractComponent)]
+pub struct VoxelFog {
+    pub color: Color,
+    /// how thick the fog is at `height`, per meter
+    pub density: f32,
+    /// height in meters the density is measured at
+    pub height: f32,
+    /// how quickly the fog thins out going up
+    pub height_falloff: f32,
+    /// distance in meters the fog stops at
+    pub max_distance: f32,
+    /// shadow rays marched along every pixel for the sun shafts, 0 is plain
+    /// height fog without shadows
+    pub sun_shaft_samples: u32,
+}
+
+impl Default for VoxelFog {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb_linear(0.5, 0.6, 0.7),
+            density: 0.02,
+            height: 0.0,
+            height_falloff: 0.1,
+            max_distance: 64.0,
+            sun_shaft_samples: 8,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum SunPosition {
+    /// the direction the sunlight travels in
+    Direction(Vec3),
+    /// hours from 0 to 24, the sun moves around the x axis
+    TimeOfDay(f32),
+}
+
+impl SunPosition {
+    /// the normalized direction the sunlight travels in
+    pub fn direction(&self) -> Vec3 {
+        match *self {
+            SunPosition::Direction(direction) => direction.normalize_or_zero(),
+            SunPosition::TimeOfDay(time_of_day) => {
+                // matches skybox in common.wgsl
+                let t = ((time_of_day + 4.0) * 15.0).to_radians();
+                -Vec3::new(0.0, -t.sin(), t.cos())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SkyModel {
+    /// sky with a sunset and sun disk that follows the sun
+    Procedural,
+    Solid(Color),
+    /// the image has to be a cube texture, like the one used by bevys `Skybox`
+    Cubemap(Handle<Image>),
+}
+
 #[derive(Clone, ShaderType)]
 pub struct TraceUniforms {
     pub camera: Mat4,
     pub camera_inverse: Mat4,
     pub last_camera: Mat4,
     pub projection: Mat4,
+    pub ray_center: Vec4,
     pub time: f32,
     pub show_ray_steps: u32,
     pub samples: u32,
     pub shadows: u32,
+    pub indirect_bounces: u32,
+    pub accumulation_frames: u32,
+    pub sun_dir: Vec3,
+    pub ambient: f32,
+    pub sun_color: Vec3,
+    pub sky_model: u32,
+    pub sky_color: Vec3,
+    pub refraction: u32,
+    pub reflection_bounces: u32,
+    pub fog_color: Vec3,
+    pub fog_density: f32,
+    pub fog_height: f32,
+    pub fog_falloff: f32,
+    pub fog_distance: f32,
+    pub fog_samples: u32,
+    pub camera_position: Vec3,
+    pub shadow_cone: f32,
+    pub shadow_distance: f32,
+    pub jitter: Vec2,
+    pub lod_bias: f32,
+    pub cone_tracing: u32,
+    pub cone_distance: f32,
+    pub max_portal_depth: u32,
+    pub portal_fallback: Vec3,
 }
 
 #[derive(Component, Deref, DerefMut)]
@@ -94,35 +343,96 @@ struct LastCameras(HashMap<Entity, Mat4>);
 
 fn prepare_uniforms(
     mut commands: Commands,
-    query: Query<(Entity, &TraceSettings, &ExtractedView)>,
-    time: Res<Time>,
+    query: Query<(Entity, &TraceSettings, &ExtractedView, Option<&VoxelFog>)>,
+    (time, frame_count): (Res<Time>, Res<FrameCount>),
+    (voxel_sky, render_graph_settings): (Res<VoxelSky>, Res<RenderGraphSettings>),
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     mut last_cameras: ResMut<LastCameras>,
 ) {
     let elapsed = time.elapsed_seconds_f64();
 
-    for (entity, settings, view) in query.iter() {
+    let sun_color =
+        Vec4::from(voxel_sky.sun_color.as_linear_rgba_f32()).truncate() * voxel_sky.sun_intensity;
+    let (sky_model, sky_color) = match &voxel_sky.model {
+        SkyModel::Procedural => (0, Vec3::ZERO),
+        SkyModel::Solid(color) => (1, Vec4::from(color.as_linear_rgba_f32()).truncate()),
+        SkyModel::Cubemap(_) => (2, Vec3::ZERO),
+    };
+
+    for (entity, settings, view, fog) in query.iter() {
         let projection = view.projection;
         let inverse_projection = projection.inverse();
+        let camera_position = view.transform.translation();
         let view = view.transform.compute_matrix();
         let inverse_view = view.inverse();
 
         let camera = projection * inverse_view;
         let camera_inverse = view * inverse_projection;
+        let ray_center = ray_center(view, projection);
 
         let last_camera = *last_cameras.get(&entity).unwrap_or(&camera);
         last_cameras.insert(entity, camera);
 
+        // sub pixel offset of the rays so the upscaler sees a different spot
+        // in every pixel each frame
+        let jitter = if settings.upscales() {
+            let index = frame_count.0 % 8 + 1;
+            Vec2::new(halton(index, 2), halton(index, 3)) - 0.5
+        } else {
+            Vec2::ZERO
+        };
+
+        // no fog is the same as fog with 0 density
+        let fog = fog.cloned().unwrap_or(VoxelFog {
+            density: 0.0,
+            ..default()
+        });
+
         let uniforms = TraceUniforms {
             camera,
             camera_inverse,
             last_camera,
             projection,
+            ray_center,
             time: elapsed as f32,
             show_ray_steps: settings.show_ray_steps as u32,
             samples: settings.samples,
             shadows: settings.shadows as u32,
+            indirect_bounces: settings.indirect_bounces,
+            accumulation_frames: settings.accumulation_frames,
+            sun_dir: voxel_sky.sun.direction(),
+            ambient: voxel_sky.ambient,
+            sun_color,
+            sky_model,
+            sky_color,
+            refraction: settings.refraction as u32,
+            reflection_bounces: if settings.reflections {
+                settings.max_reflection_bounces
+            } else {
+                0
+            },
+            fog_color: Vec4::from(fog.color.as_linear_rgba_f32()).truncate(),
+            fog_density: fog.density,
+            fog_height: fog.height,
+            fog_falloff: fog.height_falloff,
+            fog_distance: fog.max_distance,
+            fog_samples: fog.sun_shaft_samples,
+            camera_position,
+            shadow_cone: settings.shadow_cone_angle.cos(),
+            shadow_distance: settings.shadow_distance,
+            jitter,
+            // the mip texture is empty without the mip pass
+            lod_bias: if render_graph_settings.mip {
+                settings.lod_bias
+            } else {
+                0.0
+            },
+            cone_tracing: (settings.cone_tracing && render_graph_settings.mip) as u32,
+            cone_distance: settings.cone_distance,
+            // the depth shares the ray flags with the voxel flags
+            max_portal_depth: settings.max_portal_depth.min(0xFFFF),
+            portal_fallback: Vec4::from(settings.portal_fallback.as_linear_rgba_f32()).truncate(),
         };
 
         let mut uniform_buffer = UniformBuffer::from(uniforms);
@@ -134,6 +444,142 @@ fn prepare_uniforms(
     }
 }
 
+/// Where the primary rays of a view come from, see `camera_ray` in trace.wgsl.
+/// Perspective projections, off axis ones too, send every ray out of the eye
+/// at `xyz` and `w` is 1. The eye of an orthographic projection is infinitely
+/// far behind the camera, its rays all go in the direction `xyz` and `w` is 0.
+fn ray_center(view: Mat4, projection: Mat4) -> Vec4 {
+    // the point every clip space position at depth 1 lines up with
+    let center = projection.inverse() * Vec4::Z;
+    if center.w.abs() > center.truncate().length() * 1e-6 {
+        view.transform_point3(center.truncate() / center.w)
+            .extend(1.0)
+    } else {
+        // the rays go from the near plane at depth 1 towards the far plane
+        (-view.transform_vector3(center.truncate()).normalize()).extend(0.0)
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// The part of the target a camera traces into, its viewport shrunk to the
+/// render scale. `None` is the whole target.
+pub(crate) fn trace_viewport(
+    camera: &ExtractedCamera,
+    settings: &TraceSettings,
+) -> Option<Viewport> {
+    let size = camera.physical_viewport_size?;
+    if camera.viewport.is_none() && !settings.upscales() {
+        return None;
+    }
+
+    Some(Viewport {
+        physical_position: camera
+            .viewport
+            .as_ref()
+            .map_or(UVec2::ZERO, |viewport| viewport.physical_position),
+        physical_size: settings.render_size(size),
+        ..default()
+    })
+}
+
+pub(crate) fn set_viewport(render_pass: &mut wgpu::RenderPass, viewport: &Option<Viewport>) {
+    if let Some(viewport) = viewport {
+        render_pass.set_viewport(
+            viewport.physical_position.x as f32,
+            viewport.physical_position.y as f32,
+            viewport.physical_size.x as f32,
+            viewport.physical_size.y as f32,
+            viewport.depth.start,
+            viewport.depth.end,
+        );
+    }
+}
+
+/// Cameras drawing to the same target share its textures. Passes that only
+/// draw inside one camera's viewport copy the rest of the image over first, or
+/// the other cameras would see an old frame there.
+pub(crate) fn copy_outside_viewport(
+    render_context: &mut RenderContext,
+    world: &World,
+    camera: &ExtractedCamera,
+    post_process: &PostProcessWrite,
+) {
+    let (Some(viewport), Some(target_size)) = (&camera.viewport, camera.physical_target_size)
+    else {
+        return;
+    };
+
+    // the bands above and below the viewport, then left and right of it
+    let min = viewport.physical_position.min(target_size);
+    let max = (viewport.physical_position + viewport.physical_size).min(target_size);
+    let outside: Vec<(UVec2, UVec2)> = [
+        (UVec2::ZERO, UVec2::new(target_size.x, min.y)),
+        (UVec2::new(0, max.y), target_size),
+        (UVec2::new(0, min.y), UVec2::new(min.x, max.y)),
+        (UVec2::new(max.x, min.y), UVec2::new(target_size.x, max.y)),
+    ]
+    .into_iter()
+    .filter(|(from, to)| from.x < to.x && from.y < to.y)
+    .collect();
+
+    // a viewport over the whole target leaves nothing to copy
+    if outside.is_empty() {
+        return;
+    }
+
+    let trace_pipeline_data = world.resource::<TracePipelineData>();
+    let copy_pipeline = match world
+        .resource::<PipelineCache>()
+        .get_render_pipeline(trace_pipeline_data.copy_pipeline_id)
+    {
+        Some(pipeline) => pipeline,
+        None => return,
+    };
+
+    let color_bind_group = render_context.render_device().create_bind_group(
+        None,
+        &trace_pipeline_data.color_bind_group_layout,
+        &[BindGroupEntry {
+            binding: 0,
+            resource: BindingResource::TextureView(post_process.source),
+        }],
+    );
+
+    let mut render_pass =
+        render_context
+            .command_encoder()
+            .begin_render_pass(&RenderPassDescriptor {
+                label: Some("viewport copy pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: post_process.destination,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+    render_pass.set_bind_group(0, &color_bind_group, &[]);
+    render_pass.set_pipeline(copy_pipeline);
+    for (from, to) in outside {
+        let size = to - from;
+        render_pass.set_scissor_rect(from.x, from.y, size.x, size.y);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
 impl FromWorld for TracePipelineData {
     fn from_world(render_world: &mut World) -> Self {
         let voxel_data = render_world.resource::<VoxelData>();
@@ -176,9 +622,70 @@ impl FromWorld for TracePipelineData {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::Cube,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
             });
 
+        // every pixel writes the depth of its hit so meshes can be drawn on top
+        let depth_stencil = DepthStencilState {
+            format: CORE_3D_DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        };
+
         let trace_shader_handle =
             asset_server.load("embedded://bevy_voxel_engine/voxel_pipeline/trace/trace.wgsl");
         let trace_pipeline_descriptor = RenderPipelineDescriptor {
@@ -199,6 +706,167 @@ impl FromWorld for TracePipelineData {
                 })],
             }),
             primitive: PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: MultisampleState::default(),
+            push_constant_ranges: vec![],
+        };
+
+        let render_device = render_world.resource::<RenderDevice>();
+        let accumulation_bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("accumulation bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(TraceUniforms::SHADER_SIZE.into()),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: TextureFormat::Rgba16Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: TextureFormat::Rgba16Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: TextureFormat::Rgba32Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // the normals and positions of the last frame
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: TextureFormat::Rgba16Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: TextureFormat::Rgba32Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let color_bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("accumulation color bind group layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                }],
+            });
+
+        let asset_server = render_world.resource::<AssetServer>();
+        let accumulation_shader_handle: Handle<Shader> = asset_server
+            .load("embedded://bevy_voxel_engine/voxel_pipeline/trace/accumulation.wgsl");
+        let upscale_shader_handle: Handle<Shader> =
+            asset_server.load("embedded://bevy_voxel_engine/voxel_pipeline/trace/upscale.wgsl");
+        let copy_shader_handle: Handle<Shader> =
+            asset_server.load("embedded://bevy_voxel_engine/voxel_pipeline/trace/copy.wgsl");
+
+        // the upscaler binds the history in place of the accumulation
+        let accumulation_pipeline_descriptor =
+            |label: &'static str, shader: &Handle<Shader>, entry_point: &'static str| {
+                RenderPipelineDescriptor {
+                    label: Some(label.into()),
+                    layout: vec![
+                        accumulation_bind_group_layout.clone(),
+                        color_bind_group_layout.clone(),
+                    ],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader: shader.clone(),
+                        shader_defs: Vec::new(),
+                        entry_point: entry_point.into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: ViewTarget::TEXTURE_FORMAT_HDR,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                }
+            };
+        let reproject_pipeline_descriptor = accumulation_pipeline_descriptor(
+            "reproject pipeline",
+            &accumulation_shader_handle,
+            "reproject",
+        );
+        let store_pipeline_descriptor = accumulation_pipeline_descriptor(
+            "accumulation store pipeline",
+            &accumulation_shader_handle,
+            "store",
+        );
+        // the upscaled depth replaces the low resolution depth of the trace
+        let upscale_pipeline_descriptor = RenderPipelineDescriptor {
+            depth_stencil: Some(depth_stencil),
+            ..accumulation_pipeline_descriptor(
+                "temporal upscale pipeline",
+                &upscale_shader_handle,
+                "upscale",
+            )
+        };
+        let history_store_pipeline_descriptor = accumulation_pipeline_descriptor(
+            "history store pipeline",
+            &upscale_shader_handle,
+            "store_history",
+        );
+        let copy_pipeline_descriptor = RenderPipelineDescriptor {
+            label: Some("viewport copy pipeline".into()),
+            layout: vec![color_bind_group_layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: copy_shader_handle,
+                shader_defs: Vec::new(),
+                entry_point: "copy".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: ViewTarget::TEXTURE_FORMAT_HDR,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
             depth_stencil: None,
             multisample: MultisampleState::default(),
             push_constant_ranges: vec![],
@@ -206,10 +874,209 @@ impl FromWorld for TracePipelineData {
 
         let cache = render_world.resource::<PipelineCache>();
         let trace_pipeline_id = cache.queue_render_pipeline(trace_pipeline_descriptor);
+        let reproject_pipeline_id = cache.queue_render_pipeline(reproject_pipeline_descriptor);
+        let store_pipeline_id = cache.queue_render_pipeline(store_pipeline_descriptor);
+        let upscale_pipeline_id = cache.queue_render_pipeline(upscale_pipeline_descriptor);
+        let history_store_pipeline_id =
+            cache.queue_render_pipeline(history_store_pipeline_descriptor);
+        let copy_pipeline_id = cache.queue_render_pipeline(copy_pipeline_descriptor);
 
         TracePipelineData {
             trace_pipeline_id,
             trace_bind_group_layout,
+            reproject_pipeline_id,
+            store_pipeline_id,
+            upscale_pipeline_id,
+            history_store_pipeline_id,
+            copy_pipeline_id,
+            accumulation_bind_group_layout,
+            color_bind_group_layout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ray_center;
+    use bevy::{
+        asset::AssetEvent,
+        ecs::system::RunSystemOnce,
+        math::Vec3A,
+        prelude::*,
+        render::camera::{camera_system, CameraProjection, ManualTextureViews},
+        window::{PrimaryWindow, WindowCreated, WindowResized, WindowResolution},
+    };
+
+    const TARGET_SIZE: Vec2 = Vec2::new(1920.0, 1080.0);
+
+    // the same as `camera_ray` in trace.wgsl
+    fn camera_ray(view: Mat4, projection: Mat4, clip: Vec2) -> (Vec3, Vec3) {
+        let camera_inverse = view * projection.inverse();
+        let center = ray_center(view, projection);
+
+        let pos = camera_inverse.project_point3(clip.extend(1.0));
+        if center.w == 0.0 {
+            (pos, center.truncate())
+        } else {
+            (pos, (pos - center.truncate()).normalize())
+        }
+    }
+
+    // a projection matrix that is used as it is
+    #[derive(Component)]
+    struct CustomProjection(Mat4);
+
+    impl CameraProjection for CustomProjection {
+        fn get_projection_matrix(&self) -> Mat4 {
+            self.0
+        }
+
+        fn update(&mut self, _width: f32, _height: f32) {}
+
+        fn far(&self) -> f32 {
+            f32::INFINITY
         }
+
+        fn get_frustum_corners(&self, _z_near: f32, _z_far: f32) -> [Vec3A; 8] {
+            [Vec3A::ZERO; 8]
+        }
+    }
+
+    // a camera rendering to a window, set up by bevy's own camera system
+    fn bevy_camera<T: CameraProjection + Component>(projection: T) -> Camera {
+        let mut world = World::new();
+        world.init_resource::<Events<WindowResized>>();
+        world.init_resource::<Events<WindowCreated>>();
+        world.init_resource::<Events<AssetEvent<Image>>>();
+        world.init_resource::<Assets<Image>>();
+        world.init_resource::<ManualTextureViews>();
+        world.spawn((
+            Window {
+                resolution: WindowResolution::new(TARGET_SIZE.x, TARGET_SIZE.y),
+                ..default()
+            },
+            PrimaryWindow,
+        ));
+        let camera = world.spawn((Camera::default(), projection)).id();
+
+        world.run_system_once(camera_system::<T>);
+        world.entity_mut(camera).take::<Camera>().unwrap()
+    }
+
+    // the rays have to match `Camera::viewport_to_world`, so the trace sees
+    // the world the same way as bevy's meshes and picking
+    fn assert_rays_match_bevy(camera: &Camera, transform: Transform) {
+        let view = transform.compute_matrix();
+        let projection = camera.projection_matrix();
+        let global_transform = GlobalTransform::from(transform);
+
+        for x in [-1.0, -0.5, 0.0, 0.3, 1.0] {
+            for y in [-1.0, -0.2, 0.0, 0.7, 1.0] {
+                let clip = Vec2::new(x, y);
+                let (pos, dir) = camera_ray(view, projection, clip);
+
+                let viewport_position = Vec2::new(x + 1.0, 1.0 - y) / 2.0 * TARGET_SIZE;
+                let bevy_ray = camera
+                    .viewport_to_world(&global_transform, viewport_position)
+                    .unwrap();
+                let near = camera
+                    .ndc_to_world(&global_transform, clip.extend(1.0))
+                    .unwrap();
+
+                assert!(
+                    pos.abs_diff_eq(bevy_ray.origin, 1e-4) && pos.abs_diff_eq(near, 1e-4),
+                    "origin at {clip}: {pos} != {}",
+                    bevy_ray.origin
+                );
+                assert!(
+                    dir.abs_diff_eq(bevy_ray.direction, 1e-4),
+                    "direction at {clip}: {dir} != {}",
+                    bevy_ray.direction
+                );
+            }
+        }
+    }
+
+    fn camera_transform() -> Transform {
+        Transform::from_xyz(3.0, 4.0, -2.0).looking_at(Vec3::new(-1.0, 0.5, 6.0), Vec3::Y)
+    }
+
+    #[test]
+    fn perspective_rays() {
+        let transform = camera_transform();
+        let projection = PerspectiveProjection::default();
+        let camera = bevy_camera(Projection::Perspective(projection.clone()));
+        assert_rays_match_bevy(&camera, transform);
+
+        let view = transform.compute_matrix();
+        let projection_matrix = camera.projection_matrix();
+
+        // every ray starts on the near plane and points away from the eye
+        assert_eq!(ray_center(view, projection_matrix).w, 1.0);
+        let (pos, dir) = camera_ray(view, projection_matrix, Vec2::ZERO);
+        assert!(pos.abs_diff_eq(
+            transform.translation + transform.forward() * projection.near,
+            1e-4
+        ));
+        assert!(dir.abs_diff_eq(transform.forward(), 1e-4));
+
+        let (pos, dir) = camera_ray(view, projection_matrix, Vec2::ONE);
+        assert!((pos - transform.translation)
+            .normalize()
+            .abs_diff_eq(dir, 1e-4));
+        assert!(dir.dot(transform.right()) > 0.0 && dir.dot(transform.up()) > 0.0);
+    }
+
+    #[test]
+    fn orthographic_rays() {
+        let transform = camera_transform();
+        let projection = OrthographicProjection {
+            near: -5.0,
+            scaling_mode: bevy::render::camera::ScalingMode::Fixed {
+                width: 16.0,
+                height: 9.0,
+            },
+            ..default()
+        };
+        let camera = bevy_camera(Projection::Orthographic(projection.clone()));
+        assert_rays_match_bevy(&camera, transform);
+
+        let view = transform.compute_matrix();
+        let projection_matrix = camera.projection_matrix();
+
+        // the rays are parallel and start on the near plane
+        assert_eq!(ray_center(view, projection_matrix).w, 0.0);
+        for clip in [Vec2::ZERO, Vec2::ONE, Vec2::new(-1.0, 0.5)] {
+            let (_, dir) = camera_ray(view, projection_matrix, clip);
+            assert!(dir.abs_diff_eq(transform.forward(), 1e-5));
+        }
+
+        let (pos, _) = camera_ray(view, projection_matrix, Vec2::new(1.0, -1.0));
+        let expected =
+            transform.translation + transform.forward() * projection.near + transform.right() * 8.0
+                - transform.up() * 4.5;
+        assert!(pos.abs_diff_eq(expected, 1e-4), "{pos} != {expected}");
+    }
+
+    #[test]
+    fn custom_projection_rays() {
+        let transform = camera_transform();
+        let view = transform.compute_matrix();
+
+        // off axis perspective, like one eye of a stereo pair
+        let mut off_axis = Mat4::perspective_infinite_reverse_rh(1.2, 16.0 / 9.0, 0.1);
+        off_axis.z_axis.x = 0.25;
+        off_axis.z_axis.y = -0.1;
+        assert_rays_match_bevy(&bevy_camera(CustomProjection(off_axis)), transform);
+        assert!(ray_center(view, off_axis)
+            .truncate()
+            .abs_diff_eq(view.w_axis.truncate(), 1e-4));
+
+        // oblique orthographic, like a cabinet projection
+        let mut oblique = Mat4::orthographic_rh(-8.0, 8.0, -4.5, 4.5, 100.0, -5.0);
+        oblique.z_axis.x = 0.3;
+        oblique.z_axis.y = 0.2;
+        assert_rays_match_bevy(&bevy_camera(CustomProjection(oblique)), transform);
+        assert_eq!(ray_center(view, oblique).w, 0.0);
     }
 }
@@ -1,18 +1,35 @@
-use std::process::exit;
-
-use super::{TracePipelineData, ViewTraceUniformBuffer};
-use crate::voxel_pipeline::{voxel_world::VoxelData, RenderGraphSettings};
+use super::{
+    copy_outside_viewport, set_viewport, trace_viewport, SkyModel, TracePipelineData,
+    TraceSettings, ViewTraceUniformBuffer, VoxelSky,
+};
+use crate::voxel_pipeline::{
+    compute::light_volume::LightVolume, lights::LightData, voxel_world::VoxelData,
+    RenderGraphSettings,
+};
 use bevy::{
+    core::FrameCount,
     prelude::*,
     render::{
+        camera::ExtractedCamera,
+        render_asset::RenderAssets,
         render_graph::{self, SlotInfo, SlotType},
         render_resource::*,
-        view::{ExtractedView, ViewTarget},
+        texture::FallbackImage,
+        view::{ExtractedView, ViewDepthTexture, ViewTarget},
     },
 };
 
 pub struct TraceNode {
-    query: QueryState<(&'static ViewTarget, &'static ViewTraceUniformBuffer), With<ExtractedView>>,
+    query: QueryState<
+        (
+            &'static ExtractedCamera,
+            &'static ViewTarget,
+            &'static ViewDepthTexture,
+            &'static ViewTraceUniformBuffer,
+            &'static TraceSettings,
+        ),
+        With<ExtractedView>,
+    >,
 }
 
 impl TraceNode {
@@ -28,6 +45,9 @@ impl render_graph::Node for TraceNode {
         vec![
             SlotInfo::new("normal", SlotType::TextureView),
             SlotInfo::new("position", SlotType::TextureView),
+            SlotInfo::new("last_normal", SlotType::TextureView),
+            SlotInfo::new("last_position", SlotType::TextureView),
+            SlotInfo::new("accumulation", SlotType::TextureView),
         ]
     }
 
@@ -45,19 +65,21 @@ impl render_graph::Node for TraceNode {
         let pipeline_cache = world.resource::<PipelineCache>();
         let voxel_data = world.resource::<VoxelData>();
         let trace_pipeline_data = world.resource::<TracePipelineData>();
+        let light_data = world.resource::<LightData>();
+        let light_volume = world.resource::<LightVolume>();
+        let frame_count = world.resource::<FrameCount>();
         let render_graph_settings = world.resource::<RenderGraphSettings>();
 
         if !render_graph_settings.trace {
             return Ok(());
         }
 
-        let (target, trace_uniform_buffer) = match self.query.get_manual(world, view_entity) {
-            Ok(result) => result,
-            Err(err) => {
-                println!("Voxel camera missing component!: {}", err);
-                exit(1);
-            }
-        };
+        let (camera, target, depth, trace_uniform_buffer, trace_settings) =
+            match self.query.get_manual(world, view_entity) {
+                Ok(result) => result,
+                // cameras in the core 3d graph without `VoxelRender`
+                Err(_) => return Ok(()),
+            };
 
         let trace_pipeline =
             match pipeline_cache.get_render_pipeline(trace_pipeline_data.trace_pipeline_id) {
@@ -67,9 +89,25 @@ impl render_graph::Node for TraceNode {
 
         let post_process = target.post_process_write();
         let destination = post_process.destination;
+        copy_outside_viewport(render_context, world, camera, &post_process);
+
+        // a lower render scale only traces the top left of the viewport, the
+        // temporal upscaler fills in the rest
+        let viewport = trace_viewport(camera, trace_settings);
 
         let normal = graph.get_input_texture("normal")?;
         let position = graph.get_input_texture("position")?;
+        let last_normal = graph.get_input_texture("last_normal")?;
+        let last_position = graph.get_input_texture("last_position")?;
+        let accumulation = graph.get_input_texture("accumulation")?;
+
+        // the sky needs a cube texture bound even when it isn't used
+        let fallback_image = world.resource::<FallbackImage>();
+        let sky_cubemap = match &world.resource::<VoxelSky>().model {
+            SkyModel::Cubemap(handle) => world.resource::<RenderAssets<Image>>().get(handle),
+            _ => None,
+        }
+        .unwrap_or(&fallback_image.cube);
 
         let trace_bind_group = render_context.render_device().create_bind_group(
             None,
@@ -87,6 +125,30 @@ impl render_graph::Node for TraceNode {
                     binding: 2,
                     resource: BindingResource::TextureView(&position),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&sky_cubemap.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::Sampler(&sky_cubemap.sampler),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: light_data.lights_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: light_data.light_grid_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: BindingResource::TextureView(light_volume.view(frame_count)),
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: BindingResource::Sampler(&light_volume.sampler),
+                },
             ],
         );
 
@@ -100,7 +162,14 @@ impl render_graph::Node for TraceNode {
                     store: true,
                 },
             })],
-            depth_stencil_attachment: None,
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(0.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
         };
 
         {
@@ -110,11 +179,99 @@ impl render_graph::Node for TraceNode {
 
             render_pass.set_bind_group(0, &voxel_data.bind_group, &[]);
             render_pass.set_bind_group(1, &trace_bind_group, &[]);
+            set_viewport(&mut render_pass, &viewport);
 
             render_pass.set_pipeline(trace_pipeline);
             render_pass.draw(0..3, 0..1);
         }
 
+        // Temporal accumulation
+        if !trace_settings.accumulates() {
+            return Ok(());
+        }
+
+        let (reproject_pipeline, store_pipeline) = match (
+            pipeline_cache.get_render_pipeline(trace_pipeline_data.reproject_pipeline_id),
+            pipeline_cache.get_render_pipeline(trace_pipeline_data.store_pipeline_id),
+        ) {
+            (Some(reproject_pipeline), Some(store_pipeline)) => {
+                (reproject_pipeline, store_pipeline)
+            }
+            _ => return Ok(()),
+        };
+
+        let accumulation_bind_group = render_context.render_device().create_bind_group(
+            None,
+            &trace_pipeline_data.accumulation_bind_group_layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: trace_uniform_buffer.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(accumulation),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(normal),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(position),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(last_normal),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(last_position),
+                },
+            ],
+        );
+
+        // blend with the last frames, then keep the result for the next frame
+        for (label, pipeline) in [
+            ("reproject pass", reproject_pipeline),
+            ("accumulation store pass", store_pipeline),
+        ] {
+            let post_process = target.post_process_write();
+            copy_outside_viewport(render_context, world, camera, &post_process);
+
+            let color_bind_group = render_context.render_device().create_bind_group(
+                None,
+                &trace_pipeline_data.color_bind_group_layout,
+                &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(post_process.source),
+                }],
+            );
+
+            let mut render_pass =
+                render_context
+                    .command_encoder()
+                    .begin_render_pass(&RenderPassDescriptor {
+                        label: Some(label),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: post_process.destination,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Load,
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+
+            render_pass.set_bind_group(0, &accumulation_bind_group, &[]);
+            render_pass.set_bind_group(1, &color_bind_group, &[]);
+            set_viewport(&mut render_pass, &viewport);
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+
         Ok(())
     }
 }
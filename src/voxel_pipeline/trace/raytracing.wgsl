@@ -3,6 +3,10 @@
 #import bevy_voxel_engine::common::{
     VOXELS_PER_METER,
     PORTAL_FLAG,
+    TRANSPARENT_RAY,
+    REFRACT_RAY,
+    PORTAL_LIMIT_RAY,
+    PORTAL_DEPTH_SHIFT,
     VoxelUniforms,
     Ray,
     ray_plane,
@@ -12,9 +16,16 @@
 #import bevy_voxel_engine::bindings::{
     voxel_world,
     voxel_uniforms,
-    gh
+    gh,
+    mip_texture,
+    mip_material,
+    voxel_portals,
 }
 
+// how much of a mip cell has to be filled for a ray to stop at it, sparser
+// cells are traced at full detail so thin geometry doesn't grow
+const LOD_COVERAGE = 0.5;
+
 fn get_value_index(index: u32) -> bool {
     return ((gh[index / 32u] >> (index % 32u)) & 1u) != 0u;
 }
@@ -102,6 +113,8 @@ struct HitInfo {
     reprojection_pos: vec3<f32>,
     normal: vec3<f32>,
     portals: mat4x4<f32>,
+    // how much light makes it through the transparent voxels passed on the way
+    transmission: vec3<f32>,
     steps: u32,
 };
 
@@ -112,7 +125,7 @@ const IDENTITY = mat4x4<f32>(
     vec4<f32>(0.0, 0.0, 0.0, 1.0),
 );
 
-fn intersect_scene(r: Ray, steps: u32) -> HitInfo {
+fn intersect_scene(r: Ray, transmission: vec3<f32>, steps: u32) -> HitInfo {
     let rtw = f32(voxel_uniforms.texture_size) / (VOXELS_PER_METER * 2.0); // render to world ratio
 
     let normal = vec3(0.0, 1.0, 0.0);
@@ -124,20 +137,46 @@ fn intersect_scene(r: Ray, steps: u32) -> HitInfo {
         // green floor
         let color = vec3(113.0, 129.0, 44.0) / 255.0;
 
-        return HitInfo(true, 0u, vec4(color, 0.0), pos * rtw, pos * rtw, normal, IDENTITY, steps);
+        return HitInfo(true, 0u, vec4(color, 0.0), pos * rtw, pos * rtw, normal, IDENTITY, transmission, steps);
     }
 
     let infinity = 1000000000.0 * r.dir;
 
-    return HitInfo(false, 0u, vec4(0.0), infinity, infinity, vec3(0.0), IDENTITY, steps);
+    return HitInfo(false, 0u, vec4(0.0), infinity, infinity, vec3(0.0), IDENTITY, transmission, steps);
+}
+
+// the mip whose cells are about as wide as a pixel at this distance in render
+// space, 0 is the full resolution world
+fn lod_level(distance: f32, pixel_lod: vec2<f32>) -> u32 {
+    let voxels = (pixel_lod.x + distance * pixel_lod.y) * f32(voxel_uniforms.texture_size) * 0.5;
+    if voxels < 2.0 {
+        return 0u;
+    }
+    return min(u32(log2(voxels)), textureNumLevels(mip_texture) - 1u);
 }
 
 /// physics_distance is in terms of t so make sure to normalize your 
 /// ray direction if you want it to be in world cordinates.
-/// only hits voxels that have any of the flags set or hits everything if flags is 0
+/// only hits voxels that have any of the flags set or hits everything if flags is 0.
+/// TRANSPARENT_RAY lets the ray through transparent voxels, tinting the
+/// transmission, and REFRACT_RAY bends it by their ior on the way.
+/// PORTAL_LIMIT_RAY stops the ray at the next portal once it has gone through
+/// flags >> PORTAL_DEPTH_SHIFT of them, the miss has PORTAL_FLAG set in its
+/// data and the position of that portal.
 fn shoot_ray(r: Ray, physics_distance: f32, flags: u32) -> HitInfo {
+    return shoot_ray_lod(r, physics_distance, flags, vec2(0.0));
+}
+
+/// shoot_ray that stops at the averaged colour of a coarser mip once its
+/// voxels get smaller than a pixel. pixel_lod.x is the width of the pixel at
+/// the start of the ray in meters and pixel_lod.y the angle in radians it
+/// widens by. the hits have the most common material of the cell, cells
+/// with a transparent one are traced at full detail.
+fn shoot_ray_lod(r: Ray, physics_distance: f32, flags: u32, pixel_lod: vec2<f32>) -> HitInfo {
+    let voxel_flags = flags & 0xFFu;
     let wtr = VOXELS_PER_METER * 2.0 / f32(voxel_uniforms.texture_size); // world to render
     let rtw = f32(voxel_uniforms.texture_size) / (VOXELS_PER_METER * 2.0); // render to world
+    let render_lod = vec2(pixel_lod.x * wtr, pixel_lod.y);
 
     var pos = r.pos * wtr;
     let dir_mask = vec3<f32>(r.dir == vec3(0.0));
@@ -149,9 +188,9 @@ fn shoot_ray(r: Ray, physics_distance: f32, flags: u32) -> HitInfo {
         let dist = ray_box_dist(Ray(pos, dir), vec3(-1.0), vec3(1.0)).x;
         if (dist == 0.0) {
             if (physics_distance * wtr > 0.0) {
-                return HitInfo(false, 0u, vec4(0.0), (pos + dir * physics_distance * wtr) * rtw, vec3(0.0), vec3(0.0), IDENTITY, 1u);
+                return HitInfo(false, 0u, vec4(0.0), (pos + dir * physics_distance * wtr) * rtw, vec3(0.0), vec3(0.0), IDENTITY, vec3(1.0), 1u);
             }
-            return intersect_scene(Ray(pos, dir), 1u);
+            return intersect_scene(Ray(pos, dir), vec3(1.0), 1u);
         }
 
         pos = pos + dir * dist;
@@ -164,15 +203,80 @@ fn shoot_ray(r: Ray, physics_distance: f32, flags: u32) -> HitInfo {
     var normal = trunc(pos * 1.00001);
     var voxel = Voxel(0u, vec3(0.0), 0u);
     var portal_mat = IDENTITY;
+    var portal_depth = 0u;
     var reprojection_pos = pos;
+    var transmission = vec3(1.0);
+    var medium = 0u; // the transparent material the ray is in, 0 is air
+    var t_current = 0.0;
+    var lod_hit = false;
+    var lod_colour = vec4(0.0);
+    var lod_data = 0u;
     while (steps < 1000u) {
         voxel = get_value(tcpotr);
+        var t_entry = t_current;
+
+        // far away the ray only looks as deep as the mip whose cells are
+        // about the size of a pixel
+        let lod = lod_level(distance + t_current, render_lod);
+        let lod_size = voxel_uniforms.texture_size >> lod;
+        if (lod != 0u && voxel.grid_size > lod_size) {
+            let cell = vec3<i32>((tcpotr * 0.5 + 0.5) * f32(lod_size));
+            let mip = textureLoad(mip_texture, cell.zyx, i32(lod));
+            let mip_data = textureLoad(mip_material, cell.zyx, i32(lod)).r;
+            let mip_transparent = (flags & TRANSPARENT_RAY) != 0u && voxel_uniforms.materials[mip_data & 0xFFu].transparency > 0.0;
+            if (mip.a >= LOD_COVERAGE && !mip_transparent && (((mip_data >> 8u) & voxel_flags) > 0u || voxel_flags == 0u)) {
+                lod_hit = true;
+                lod_colour = vec4(mip.rgb, 1.0);
+                lod_data = mip_data;
+                break;
+            }
+            // empty cells are skipped, partly filled ones traced at full detail
+            if (mip.a == 0.0) {
+                let rounded_pos = ((vec3<f32>(cell) + 0.5) / f32(lod_size)) * 2.0 - 1.0;
+                voxel = Voxel(0u, rounded_pos, lod_size);
+            }
+        }
 
+        let material = voxel.data & 0xFFu;
         let should_portal_skip = ((voxel.data >> 8u) & PORTAL_FLAG) > 0u;
-        if ((voxel.data & 0xFFu) != 0u && !should_portal_skip && (((voxel.data >> 8u) & flags) > 0u || flags == 0u)) {
+        let transparent = (flags & TRANSPARENT_RAY) != 0u && material != 0u && !should_portal_skip && voxel_uniforms.materials[material].transparency > 0.0;
+        if (material != 0u && !should_portal_skip && !transparent && (((voxel.data >> 8u) & voxel_flags) > 0u || voxel_flags == 0u)) {
             break;
         }
 
+        // crossing into or out of a transparent material
+        let next_medium = select(0u, material, transparent);
+        if (next_medium != medium) {
+            if ((flags & REFRACT_RAY) != 0u && steps > 0u) {
+                let ior_from = select(1.0, voxel_uniforms.materials[medium].ior, medium != 0u);
+                let ior_to = select(1.0, voxel_uniforms.materials[next_medium].ior, next_medium != 0u);
+                let refracted = refract(dir, normal, ior_from / ior_to);
+
+                distance += t_current;
+                t_current = 0.0;
+                t_entry = 0.0;
+                pos = tcpotr;
+
+                // total internal reflection, bounce back into the material
+                if (all(refracted == vec3(0.0))) {
+                    dir = reflect(dir, normal);
+                    r_sign = sign(dir);
+                    pos = tcpotr + normal * 0.000004;
+                    tcpotr = pos;
+                    steps = steps + 1u;
+                    continue;
+                }
+
+                dir = refracted;
+                r_sign = sign(dir);
+            }
+
+            if (next_medium != 0u) {
+                transmission *= voxel_uniforms.materials[next_medium].transparency;
+            }
+            medium = next_medium;
+        }
+
         let voxel_size = 2.0 / f32(voxel.grid_size);
         let t_max = (voxel.pos - pos + r_sign * voxel_size / 2.0) / dir;
 
@@ -180,16 +284,27 @@ fn shoot_ray(r: Ray, physics_distance: f32, flags: u32) -> HitInfo {
         let mask = vec3<f32>(t_max.xyz <= min(t_max.yzx, t_max.zxy));
         normal = mask * -r_sign;
 
-        let t_current = min(min(t_max.x, t_max.y), t_max.z);
+        t_current = min(min(t_max.x, t_max.y), t_max.z);
         tcpotr = pos + dir * t_current - normal * 0.000002;
         reprojection_pos = r.pos + (t_current + distance) * r.dir * rtw;
 
-        // portals
-        if (should_portal_skip) {
-            let portal = voxel_uniforms.portals[i32(voxel.data & 0xFFu)];
+        // the colour of the material is absorbed per meter travelled through it
+        if (medium != 0u) {
+            let meters = max(t_current - t_entry, 0.0) * rtw;
+            transmission *= pow(voxel_uniforms.pallete[medium].rgb, vec3(meters));
+        }
+
+        // portals, disabled ones have no normal and are see through
+        if (should_portal_skip && any(voxel_portals[voxel.data & 0xFFu].normal != vec3(0.0))) {
+            let portal = voxel_portals[voxel.data & 0xFFu];
 
             let intersection = ray_plane(Ray(pos * rtw, dir), portal.position + portal.normal * 0.00002, portal.normal);
             if (intersection.w != 0.0 && intersection.w * wtr < t_current) {
+                if ((flags & PORTAL_LIMIT_RAY) != 0u && portal_depth >= flags >> PORTAL_DEPTH_SHIFT) {
+                    return HitInfo(false, PORTAL_FLAG << 8u, vec4(0.0), intersection.xyz, reprojection_pos, portal.normal, portal_mat, transmission, steps);
+                }
+                portal_depth += 1u;
+
                 pos = (portal.transformation * vec4(intersection.xyz - portal.normal * 0.00004, 1.0)).xyz * wtr;
                 dir = (portal.transformation * vec4(dir, 0.0)).xyz;
                 r_sign = sign(dir);
@@ -202,18 +317,21 @@ fn shoot_ray(r: Ray, physics_distance: f32, flags: u32) -> HitInfo {
         }
 
         if (t_current + distance > physics_distance * wtr && physics_distance > 0.0) {
-            return HitInfo(false, 0u, vec4(0.0), (pos + dir * (physics_distance * wtr - distance)) * rtw, vec3(0.0), vec3(0.0), portal_mat, steps);
+            return HitInfo(false, 0u, vec4(0.0), (pos + dir * (physics_distance * wtr - distance)) * rtw, vec3(0.0), vec3(0.0), portal_mat, transmission, steps);
         }
 
         if (!in_bounds(tcpotr)) {
             if (physics_distance > 0.0) {
-                return HitInfo(false, 0u, vec4(0.0), (pos + dir * (physics_distance * wtr - distance)) * rtw, vec3(0.0), vec3(0.0), portal_mat, steps);
+                return HitInfo(false, 0u, vec4(0.0), (pos + dir * (physics_distance * wtr - distance)) * rtw, vec3(0.0), vec3(0.0), portal_mat, transmission, steps);
             }
-            return intersect_scene(Ray(pos, dir), steps);
+            return intersect_scene(Ray(pos, dir), transmission, steps);
         }
 
         steps = steps + 1u;
     }
 
-    return HitInfo(true, voxel.data, voxel_uniforms.materials[voxel.data & 0xFFu], tcpotr * rtw + normal * 0.0001, reprojection_pos, normal, portal_mat, steps);
+    if (lod_hit) {
+        return HitInfo(true, lod_data, lod_colour, tcpotr * rtw + normal * 0.0001, reprojection_pos, normal, portal_mat, transmission, steps);
+    }
+    return HitInfo(true, voxel.data, voxel_uniforms.pallete[voxel.data & 0xFFu], tcpotr * rtw + normal * 0.0001, reprojection_pos, normal, portal_mat, transmission, steps);
 }
\ No newline at end of file
@@ -3,58 +3,416 @@
     VOXELS_PER_METER,
     PI,
     VoxelUniforms,
+    VoxelMaterial,
     TraceUniforms,
     Ray,
-    skybox
+    TRANSPARENT_RAY,
+    REFRACT_RAY,
+    PORTAL_LIMIT_RAY,
+    PORTAL_DEPTH_SHIFT,
+    PORTAL_FLAG,
+    procedural_sky,
+    cosine_hemisphere,
+    sample_cone,
+    hash
 }
 #import bevy_voxel_engine::raytracing::{
+    HitInfo,
     shoot_ray,
+    shoot_ray_lod,
 }
 #import bevy_voxel_engine::bindings::{
     voxel_world,
     voxel_uniforms,
-    gh
+    gh,
+    mip_texture,
+    texture_sampler,
 }
 
-@group(0) @binding(3)
-var texture_sampler: sampler;
-
 @group(1) @binding(0)
 var<uniform> trace_uniforms: TraceUniforms;
 @group(1) @binding(1)
 var normal: texture_storage_2d<rgba16float, read_write>;
 @group(1) @binding(2)
 var position: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(3)
+var sky_cubemap: texture_cube<f32>;
+@group(1) @binding(4)
+var sky_sampler: sampler;
+@group(1) @binding(5)
+var<storage, read> lights: Lights;
+// offset into the indices for every cell followed by the light indices
+@group(1) @binding(6)
+var<storage, read> light_grid: array<u32>;
+// light of the emissive voxels spread out through the world
+@group(1) @binding(7)
+var light_volume: texture_3d<f32>;
+@group(1) @binding(8)
+var light_volume_sampler: sampler;
 
-struct DirectLightningInfo {
+struct Light {
+    position: vec3<f32>,
+    range: f32,
     color: vec3<f32>,
-    shadow: f32,
+    cos_outer_angle: f32,
+    direction: vec3<f32>,
+    cos_inner_angle: f32,
+};
+
+struct Lights {
+    count: u32,
+    lights: array<Light>,
 };
 
-fn calculate_direct(material: vec4<f32>, pos: vec3<f32>, normal: vec3<f32>, seed: vec3<u32>, shadow_samples: u32) -> DirectLightningInfo {
-    // Diffuse
-    let diffuse = max(dot(normal, -normalize(SUN_DIR)), 0.0);
+const LIGHT_GRID_SIZE = 16u;
+const LIGHT_VOLUME_SIZE = 64.0;
 
-    // Shadow
-    var shadow = 1.0;
+// light from emissive voxels reaching pos, a little in front of the surface
+// so solid cells don't block it
+fn emissive_light(pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let voxels_per_cell = max(f32(voxel_uniforms.texture_size) / LIGHT_VOLUME_SIZE, 1.0);
+    let cell_size = voxels_per_cell / VOXELS_PER_METER;
+    let texture_coords = (pos + normal * cell_size * 0.5) * VOXELS_PER_METER + f32(voxel_uniforms.texture_size) / 2.0;
+    let uvw = texture_coords / (voxels_per_cell * LIGHT_VOLUME_SIZE);
+    return textureSampleLevel(light_volume, light_volume_sampler, uvw, 0.0).rgb;
+}
+
+fn light_cell(pos: vec3<f32>) -> u32 {
+    let half_size = f32(voxel_uniforms.texture_size) / (2.0 * VOXELS_PER_METER);
+    let cell_size = 2.0 * half_size / f32(LIGHT_GRID_SIZE);
+    let cell = clamp(vec3<i32>(floor((pos + half_size) / cell_size)), vec3(0), vec3(i32(LIGHT_GRID_SIZE) - 1));
+    return u32(cell.x) + u32(cell.y) * LIGHT_GRID_SIZE + u32(cell.z) * LIGHT_GRID_SIZE * LIGHT_GRID_SIZE;
+}
 
-    if trace_uniforms.shadows != 0u {
-        let shadow_ray = Ray(pos, -normalize(SUN_DIR));
-        let shadow_hit = shoot_ray(shadow_ray, 0.0, 0u);
-        shadow = f32(!shadow_hit.hit);
+// shadows are turned off or the position is past the shadow distance
+fn casts_shadows(pos: vec3<f32>) -> bool {
+    if trace_uniforms.shadows == 0u {
+        return false;
     }
+    return trace_uniforms.shadow_distance <= 0.0 || distance(pos, trace_uniforms.camera_position) < trace_uniforms.shadow_distance;
+}
+
+// the point and spot lights that reach the cell the position is in
+fn calculate_lights(pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var color = vec3(0.0);
+    if lights.count == 0u {
+        return color;
+    }
+
+    let cell = light_cell(pos);
+    for (var i = light_grid[cell]; i < light_grid[cell + 1u]; i++) {
+        let light = lights.lights[light_grid[i]];
+
+        let to_light = light.position - pos;
+        let distance = length(to_light);
+        let dir = to_light / distance;
+        let diffuse = dot(normal, dir);
+        if distance >= light.range || diffuse <= 0.0 {
+            continue;
+        }
+
+        // smoothly fade out towards the range
+        let window = pow(clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0), 2.0);
+        let attenuation = window / (distance * distance + 1.0);
+        let cone = smoothstep(light.cos_outer_angle, light.cos_inner_angle, dot(-dir, light.direction));
+        if attenuation * cone <= 0.0 {
+            continue;
+        }
+
+        var shadow = vec3(1.0);
+        if casts_shadows(pos) {
+            let shadow_hit = shoot_ray(Ray(pos, dir), distance, TRANSPARENT_RAY);
+            if shadow_hit.hit {
+                continue;
+            }
+            shadow = shadow_hit.transmission;
+        }
+
+        color += light.color * diffuse * attenuation * cone * shadow;
+    }
+
+    return color;
+}
+
+// the light coming from the sky in a direction
+fn sky(dir: vec3<f32>) -> vec3<f32> {
+    switch trace_uniforms.sky_model {
+        // Procedural
+        case 0u: {
+            return procedural_sky(dir, -trace_uniforms.sun_dir);
+        }
+        // Cubemap
+        case 2u: {
+            return textureSampleLevel(sky_cubemap, sky_sampler, dir, 0.0).rgb;
+        }
+        // Solid
+        default: {
+            return trace_uniforms.sky_color;
+        }
+    }
+}
+
+struct DirectLightningInfo {
+    color: vec3<f32>,
+    // coloured by the transparent voxels in the way
+    shadow: vec3<f32>,
+};
+
+fn calculate_direct(pos: vec3<f32>, normal: vec3<f32>, seed: vec3<u32>, shadow_samples: u32) -> DirectLightningInfo {
+    let sun_dir = trace_uniforms.sun_dir;
+
+    // Diffuse, the sun is hidden below the horizon
+    var diffuse = max(dot(normal, -sun_dir), 0.0);
+    if sun_dir.y > 0.0 {
+        diffuse = 0.0;
+    }
+
+    // Shadow, soft shadows average rays spread over the cone of the sun
+    var shadow = vec3(1.0);
 
-    // Emissive
-    var emissive = vec3(0.0);
-    if material.a != 0.0 {
-        emissive = vec3(material.rgb);
+    if casts_shadows(pos) && diffuse > 0.0 {
+        var shadow_rays = 1u;
+        if trace_uniforms.shadows == 2u {
+            shadow_rays = max(shadow_samples, 1u);
+        }
+
+        shadow = vec3(0.0);
+        for (var i = 0u; i < shadow_rays; i++) {
+            var dir = -sun_dir;
+            if trace_uniforms.shadows == 2u {
+                dir = sample_cone(dir, trace_uniforms.shadow_cone, seed + vec3(i * 7u));
+            }
+
+            let shadow_hit = shoot_ray(Ray(pos, dir), 0.0, TRANSPARENT_RAY);
+            shadow += shadow_hit.transmission * f32(!shadow_hit.hit);
+        }
+        shadow /= f32(shadow_rays);
     }
 
-    let color = diffuse * shadow + emissive;
+    let color = diffuse * shadow * trace_uniforms.sun_color + calculate_lights(pos, normal);
 
     return DirectLightningInfo(color, shadow);
 }
 
+// light given off by the voxel itself
+fn emission(voxel: u32) -> vec3<f32> {
+    let material = voxel & 0xFFu;
+    return voxel_uniforms.pallete[material].rgb * voxel_uniforms.materials[material].emission;
+}
+
+// how much light is reflected off of the surface instead of going into it
+fn fresnel(material: VoxelMaterial, colour: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    let dielectric = pow((material.ior - 1.0) / (material.ior + 1.0), 2.0);
+    let f0 = mix(vec3(dielectric), colour, material.metallic);
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// how much of the light is scattered diffusely, metals reflect all of it
+fn diffuse_weight(material: VoxelMaterial) -> f32 {
+    return 1.0 - material.metallic;
+}
+
+// Light arriving from the mirrored direction, jittered by the roughness.
+// Follows the reflections of glossy voxels it hits up to reflection_bounces.
+fn calculate_reflection(pos: vec3<f32>, normal: vec3<f32>, view: vec3<f32>, roughness: f32, seed: vec3<u32>) -> vec3<f32> {
+    var color = vec3(0.0);
+    var throughput = vec3(1.0);
+    var ray_pos = pos;
+    var ray_normal = normal;
+    var ray_view = view;
+    var ray_roughness = roughness;
+
+    for (var bounce = 0u; bounce < trace_uniforms.reflection_bounces; bounce++) {
+        let bounce_seed = seed + vec3(bounce * 5u + 3u);
+        let mirrored = reflect(-ray_view, ray_normal);
+        let jitter = cosine_hemisphere(ray_normal, bounce_seed);
+        let dir = normalize(mix(mirrored, jitter, ray_roughness * ray_roughness));
+
+        let hit = shoot_ray(Ray(ray_pos, dir), 0.0, ray_flags());
+        throughput *= hit.transmission;
+
+        if !hit.hit {
+            color += throughput * miss_color(hit, dir) * trace_uniforms.ambient;
+            break;
+        }
+
+        // reflected surfaces only get the sky as ambient light
+        let material = voxel_uniforms.materials[hit.data & 0xFFu];
+        let direct_lighting = calculate_direct(hit.pos, hit.normal, bounce_seed + 1u, 1u);
+        let ambient = sky(hit.normal) * trace_uniforms.ambient;
+        color += throughput * ((direct_lighting.color + ambient) * hit.material.rgb * diffuse_weight(material) + emission(hit.data));
+
+        // keep going if the hit surface is glossy as well
+        let smoothness = 1.0 - material.roughness;
+        throughput *= fresnel(material, hit.material.rgb, max(dot(hit.normal, -dir), 0.0)) * smoothness;
+        if all(throughput < vec3(0.001)) {
+            break;
+        }
+
+        ray_pos = hit.pos;
+        ray_normal = hit.normal;
+        ray_view = -dir;
+        ray_roughness = material.roughness;
+    }
+
+    return color;
+}
+
+// Light reflected towards the viewer, the sun highlight and what is in the
+// mirrored direction. Fully rough surfaces only scatter diffusely.
+fn calculate_specular(voxel: u32, pos: vec3<f32>, normal: vec3<f32>, view: vec3<f32>, shadow: vec3<f32>, seed: vec3<u32>) -> vec3<f32> {
+    let material = voxel_uniforms.materials[voxel & 0xFFu];
+    let colour = voxel_uniforms.pallete[voxel & 0xFFu].rgb;
+    let smoothness = 1.0 - material.roughness;
+    if smoothness <= 0.0 {
+        return vec3(0.0);
+    }
+
+    let reflectance = fresnel(material, colour, max(dot(normal, view), 0.0)) * smoothness;
+
+    // without traced reflections rough surfaces blur the sky towards the
+    // light above the surface
+    var environment = vec3(0.0);
+    if trace_uniforms.reflection_bounces != 0u {
+        environment = calculate_reflection(pos, normal, view, material.roughness, seed);
+    } else {
+        let reflection = reflect(-view, normal);
+        environment = mix(sky(normal), sky(reflection), smoothness) * trace_uniforms.ambient;
+    }
+
+    // normalized blinn-phong highlight from the sun
+    var highlight = vec3(0.0);
+    let sun_dir = -trace_uniforms.sun_dir;
+    let n_dot_l = dot(normal, sun_dir);
+    if trace_uniforms.sun_dir.y <= 0.0 && n_dot_l > 0.0 {
+        let half_dir = normalize(sun_dir + view);
+        let shininess = 2.0 / max(pow(material.roughness, 4.0), 0.0001) - 2.0;
+        let distribution = (shininess + 8.0) / (8.0 * PI) * pow(max(dot(normal, half_dir), 0.0), shininess);
+        highlight = distribution * n_dot_l * shadow * trace_uniforms.sun_color;
+    }
+
+    return reflectance * (environment + highlight);
+}
+
+// flags for the rays that see the world, they go through transparent voxels
+// and as many portals as the settings allow
+fn ray_flags() -> u32 {
+    var flags = TRANSPARENT_RAY | PORTAL_LIMIT_RAY | (trace_uniforms.max_portal_depth << PORTAL_DEPTH_SHIFT);
+    if trace_uniforms.refraction != 0u {
+        flags |= REFRACT_RAY;
+    }
+    return flags;
+}
+
+// whether the ray stopped at a portal past the max portal depth
+fn portal_limited(hit: HitInfo) -> bool {
+    return !hit.hit && ((hit.data >> 8u) & PORTAL_FLAG) != 0u;
+}
+
+// what a ray that didn't hit a voxel sees
+fn miss_color(hit: HitInfo, dir: vec3<f32>) -> vec3<f32> {
+    if portal_limited(hit) {
+        return trace_uniforms.portal_fallback;
+    }
+    return sky(dir);
+}
+
+// Follows cosine weighted bounces off of the hit surface, adding up the light
+// from the sun and emissive voxels that reaches it
+fn calculate_indirect(pos: vec3<f32>, normal: vec3<f32>, seed: vec3<u32>) -> vec3<f32> {
+    var color = vec3(0.0);
+    var throughput = vec3(1.0);
+    var ray_pos = pos;
+    var ray_normal = normal;
+
+    for (var bounce = 0u; bounce < trace_uniforms.indirect_bounces; bounce++) {
+        let bounce_seed = seed + vec3(bounce * 3u + 1u);
+        let dir = cosine_hemisphere(ray_normal, bounce_seed);
+        let hit = shoot_ray(Ray(ray_pos, dir), 0.0, ray_flags());
+        throughput *= hit.transmission;
+
+        if !hit.hit {
+            color += throughput * miss_color(hit, dir) * trace_uniforms.ambient;
+            break;
+        }
+
+        let direct_lighting = calculate_direct(hit.pos, hit.normal, bounce_seed + 2u, 1u);
+        let diffuse = diffuse_weight(voxel_uniforms.materials[hit.data & 0xFFu]);
+        color += throughput * (direct_lighting.color * hit.material.rgb * diffuse + emission(hit.data));
+
+        // the cosine term and the pdf cancel out, leaving only the albedo
+        throughput *= hit.material.rgb;
+        if all(throughput < vec3(0.001)) {
+            break;
+        }
+
+        ray_pos = hit.pos;
+        ray_normal = hit.normal;
+    }
+
+    return color;
+}
+
+// how much of the light scatters forwards through the fog
+const FOG_ANISOTROPY = 0.5;
+
+fn fog_density(pos: vec3<f32>) -> f32 {
+    return trace_uniforms.fog_density * exp(-trace_uniforms.fog_falloff * (pos.y - trace_uniforms.fog_height));
+}
+
+// henyey-greenstein
+fn fog_phase(cos_theta: f32) -> f32 {
+    let g2 = FOG_ANISOTROPY * FOG_ANISOTROPY;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * FOG_ANISOTROPY * cos_theta, 1.5));
+}
+
+// Height fog between the camera and the hit. With fog_samples the ray is
+// marched with shadow rays towards the sun for the sun shafts, otherwise the
+// fog is integrated in one go without shadows.
+fn apply_fog(color: vec3<f32>, pos: vec3<f32>, dir: vec3<f32>, hit_distance: f32, seed: vec3<u32>) -> vec3<f32> {
+    if trace_uniforms.fog_density <= 0.0 {
+        return color;
+    }
+
+    let distance = min(hit_distance, trace_uniforms.fog_distance);
+    let ambient = trace_uniforms.fog_color * trace_uniforms.ambient;
+    let sun_dir = -trace_uniforms.sun_dir;
+    var sun = trace_uniforms.sun_color * fog_phase(dot(dir, sun_dir));
+    if trace_uniforms.sun_dir.y > 0.0 {
+        sun = vec3(0.0);
+    }
+
+    if trace_uniforms.fog_samples == 0u {
+        let falloff = trace_uniforms.fog_falloff * dir.y;
+        var optical_depth = fog_density(pos) * distance;
+        if abs(falloff) > 0.0001 {
+            optical_depth = fog_density(pos) * (1.0 - exp(-falloff * distance)) / falloff;
+        }
+        let transmittance = exp(-optical_depth);
+        return color * transmittance + (ambient + sun) * (1.0 - transmittance);
+    }
+
+    let step = distance / f32(trace_uniforms.fog_samples);
+    let jitter = hash(seed).x;
+    var transmittance = 1.0;
+    var light = vec3(0.0);
+    for (var i = 0u; i < trace_uniforms.fog_samples; i++) {
+        let sample_pos = pos + dir * (f32(i) + jitter) * step;
+
+        var sun_light = sun;
+        if any(sun > vec3(0.0)) {
+            let shadow_hit = shoot_ray(Ray(sample_pos, sun_dir), 0.0, TRANSPARENT_RAY);
+            sun_light *= shadow_hit.transmission * f32(!shadow_hit.hit);
+        }
+
+        let step_transmittance = exp(-fog_density(sample_pos) * step);
+        light += transmittance * (ambient + sun_light) * (1.0 - step_transmittance);
+        transmittance *= step_transmittance;
+    }
+
+    return color * transmittance + light;
+}
+
 fn get_voxel(pos: vec3<f32>) -> f32 {
     if any(pos < vec3(0.0)) || any(pos >= vec3(f32(voxel_uniforms.texture_size))) {
         return 0.0;
@@ -82,46 +440,167 @@ fn voxel_ao(pos: vec3<f32>, d1: vec3<f32>, d2: vec3<f32>) -> vec4<f32> {
     return 1.0 - ao;
 }
 
+// tan of half the angle of a cone, 6 cones of 60 degrees cover the hemisphere
+const CONE_TAN = 0.577;
+const CONE_STEPS = 16u;
+
+// Marches a cone through the filtered mip texture. The mip only has the
+// colours of the voxels so they are lit by occluder_light and the sun, without
+// shadows. Returns the light reaching pos down the cone and how much of the
+// cone is blocked.
+fn trace_cone(pos: vec3<f32>, dir: vec3<f32>, occluder_light: vec3<f32>) -> vec4<f32> {
+    let texture_size = f32(voxel_uniforms.texture_size);
+    let max_distance = trace_uniforms.cone_distance * VOXELS_PER_METER;
+    let origin = pos * VOXELS_PER_METER + texture_size * 0.5;
+
+    // the sides of the voxels facing back down the cone that the sun shines on
+    var sun = vec3(0.0);
+    if trace_uniforms.sun_dir.y <= 0.0 {
+        sun = trace_uniforms.sun_color * max(dot(dir, trace_uniforms.sun_dir), 0.0);
+    }
+
+    var light = vec3(0.0);
+    var occlusion = 0.0;
+    // starts a voxel out so the surface doesn't block itself
+    var distance = 1.0;
+    for (var i = 0u; i < CONE_STEPS && distance < max_distance && occlusion < 1.0; i++) {
+        let diameter = max(2.0 * CONE_TAN * distance, 1.0);
+        let uvw = ((origin + dir * distance) / texture_size).zyx;
+        let voxels = textureSampleLevel(mip_texture, texture_sampler, uvw, log2(diameter));
+
+        let weight = (1.0 - occlusion) * voxels.a;
+        light += weight * voxels.rgb * (occluder_light + sun);
+        occlusion += weight;
+        distance += diameter * 0.5;
+    }
+
+    // the rest of the cone sees the sky
+    light += (1.0 - occlusion) * sky(dir) * trace_uniforms.ambient;
+    return vec4(light, occlusion);
+}
+
+// Light from the sky and one bounce off of the voxels around pos through 6
+// cones, a lot cheaper than following rays with indirect_bounces. The alpha is
+// how much of the light is blocked.
+fn cone_traced_light(pos: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    let b = normalize(cross(normal, vec3(0.0, 1.0, 1.0)));
+    let t = cross(b, normal);
+    let occluder_light = sky(vec3(0.0, 1.0, 0.0)) * trace_uniforms.ambient;
+
+    // one cone along the normal and five around it, weighted by their cosine
+    var light = trace_cone(pos, normal, occluder_light) * 0.25;
+    for (var i = 0u; i < 5u; i++) {
+        let angle = f32(i) * 2.0 * PI / 5.0;
+        let dir = normalize(normal * 0.5 + (cos(angle) * b + sin(angle) * t) * 0.866);
+        light += trace_cone(pos, dir, occluder_light) * 0.15;
+    }
+
+    return light;
+}
+
 fn glmod(x: vec2<f32>, y: vec2<f32>) -> vec2<f32> {
     return x - y * floor(x / y);
 }
 
-const SUN_DIR: vec3<f32> = vec3(0.4, -0.6, 0.8);
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+// depth of the hit in the same reversed z as bevy's meshes, so they can be
+// drawn over the voxels. misses are infinitely far away, apart from the
+// portals the ray stopped at.
+fn hit_depth(hit: HitInfo) -> f32 {
+    if !hit.hit && !portal_limited(hit) {
+        return 0.0;
+    }
+    // the reprojection position is where the hit appears to be, through portals
+    let clip = trace_uniforms.camera * vec4(hit.reprojection_pos, 1.0);
+    return clamp(clip.z / clip.w, 0.0, 1.0);
+}
+
+// ray from the near plane of the camera through a point on the screen, like
+// bevy's `Camera::viewport_to_world`
+fn camera_ray(clip_space: vec2<f32>) -> Ray {
+    let near = trace_uniforms.camera_inverse * vec4(clip_space, 1.0, 1.0);
+    let pos = near.xyz / near.w;
+    // orthographic rays are all parallel
+    if trace_uniforms.ray_center.w == 0.0 {
+        return Ray(pos, trace_uniforms.ray_center.xyz);
+    }
+    return Ray(pos, normalize(pos - trace_uniforms.ray_center.xyz));
+}
 
 @fragment
-fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     let seed = vec3<u32>(in.position.xyz) * 100u + u32(trace_uniforms.time * 120.0) * 15236u;
     let resolution = vec2<f32>(textureDimensions(normal));
-    var clip_space = vec2(1.0, -1.0) * (in.uv * 2.0 - 1.0);
+    // the jitter moves the ray around inside the pixel for the upscaler
+    let uv = in.uv + trace_uniforms.jitter / resolution;
+    var clip_space = vec2(1.0, -1.0) * (uv * 2.0 - 1.0);
     var output_color = vec3(0.0);
 
-    let pos1 = trace_uniforms.camera_inverse * vec4(clip_space.x, clip_space.y, 1.0, 1.0);
-    let dir1 = trace_uniforms.camera_inverse * vec4(clip_space.x, clip_space.y, 0.01, 1.0);
-    let pos = pos1.xyz / pos1.w;
-    let dir = normalize(dir1.xyz / dir1.w - pos);
-    var ray = Ray(pos, dir);
+    let ray = camera_ray(clip_space);
+    let pos = ray.pos;
+    let dir = ray.dir;
 
-    let hit = shoot_ray(ray, 0.0, 0u);
+    // how far apart the rays of neighbouring pixels get decides how coarse a
+    // mip the ray can stop at. Perspective rays spread out by an angle,
+    // orthographic ones stay a pixel apart.
+    let next_ray = camera_ray(clip_space + vec2(2.0 / resolution.x, 0.0));
+    let pixel_lod = vec2(distance(next_ray.pos, pos), length(next_ray.dir - dir));
+
+    let lod = pixel_lod * trace_uniforms.lod_bias;
+
+    // the first surface in the way reflects part of the light when it is
+    // transparent, the rest goes through it
+    var hit = shoot_ray_lod(ray, 0.0, ray_flags() & ~TRANSPARENT_RAY, lod);
+    var glass_reflection = vec3(0.0);
+    var glass_transmission = vec3(1.0);
+    let surface = voxel_uniforms.materials[hit.data & 0xFFu];
+    if hit.hit && (hit.data & 0xFFu) != 0u && surface.transparency > 0.0 {
+        let colour = voxel_uniforms.pallete[hit.data & 0xFFu].rgb;
+        let shadow = calculate_direct(hit.pos, hit.normal, seed + 5u, 1u).shadow;
+        glass_reflection = calculate_specular(hit.data, hit.pos, hit.normal, -dir, shadow, seed + 6u);
+        glass_transmission = 1.0 - fresnel(surface, colour, max(dot(hit.normal, -dir), 0.0)) * (1.0 - surface.roughness);
+        hit = shoot_ray_lod(ray, 0.0, ray_flags(), lod);
+    }
     var steps = hit.steps;
 
     var samples = 0.0;
     if hit.hit {
         // Direct lighting
-        let direct_lighting = calculate_direct(hit.material, hit.pos, hit.normal, seed + 1u, trace_uniforms.samples);
+        let direct_lighting = calculate_direct(hit.pos, hit.normal, seed + 1u, trace_uniforms.samples);
 
         // Indirect lighting
-        let texture_coords = hit.pos * VOXELS_PER_METER + f32(voxel_uniforms.texture_size) / 2.0;
-        let ao = voxel_ao(texture_coords, hit.normal.zxy, hit.normal.yzx);
-        let uv = glmod(vec2(dot(hit.normal * texture_coords.yzx, vec3(1.0)), dot(hit.normal * texture_coords.zxy, vec3(1.0))), vec2(1.0));
+        var indirect_lighting_color = vec3(0.0);
+        if trace_uniforms.indirect_bounces != 0u {
+            let sample_count = max(trace_uniforms.samples, 1u);
+            for (var i = 0u; i < sample_count; i++) {
+                indirect_lighting_color += calculate_indirect(hit.pos, hit.normal, seed + vec3(i * 7919u + 2u));
+            }
+            indirect_lighting_color /= f32(sample_count);
+        } else if trace_uniforms.cone_tracing != 0u {
+            let cone_light = cone_traced_light(hit.pos, hit.normal);
+            indirect_lighting_color = cone_light.rgb + emissive_light(hit.pos, hit.normal) * (1.0 - cone_light.a);
+        } else {
+            let texture_coords = hit.pos * VOXELS_PER_METER + f32(voxel_uniforms.texture_size) / 2.0;
+            let ao = voxel_ao(texture_coords, hit.normal.zxy, hit.normal.yzx);
+            let uv = glmod(vec2(dot(hit.normal * texture_coords.yzx, vec3(1.0)), dot(hit.normal * texture_coords.zxy, vec3(1.0))), vec2(1.0));
 
-        let interpolated_ao_pweig = mix(mix(ao.z, ao.w, uv.x), mix(ao.y, ao.x, uv.x), uv.y);
-        let voxel_ao = pow(interpolated_ao_pweig, 1.0 / 3.0);
-        let indirect_lighting_color = vec3(0.3 * voxel_ao);
+            let interpolated_ao_pweig = mix(mix(ao.z, ao.w, uv.x), mix(ao.y, ao.x, uv.x), uv.y);
+            let voxel_ao = pow(interpolated_ao_pweig, 1.0 / 3.0);
+            indirect_lighting_color = (sky(hit.normal) * trace_uniforms.ambient + emissive_light(hit.pos, hit.normal)) * voxel_ao;
+        }
 
-        output_color = (indirect_lighting_color + direct_lighting.color) * hit.material.rgb;
+        let diffuse = diffuse_weight(voxel_uniforms.materials[hit.data & 0xFFu]);
+        let specular = calculate_specular(hit.data, hit.pos, hit.normal, -dir, direct_lighting.shadow, seed + 3u);
+        output_color = (indirect_lighting_color + direct_lighting.color) * hit.material.rgb * diffuse + specular + emission(hit.data);
     } else {
-        output_color = vec3(0.3);
+        output_color = miss_color(hit, dir);
     }
+    output_color = output_color * hit.transmission * glass_transmission + glass_reflection;
+    output_color = apply_fog(output_color, pos, dir, length(hit.reprojection_pos - pos), seed + 4u);
 
     if trace_uniforms.show_ray_steps != 0u {
         output_color = vec3<f32>(f32(steps) / 100.0);
@@ -129,8 +608,11 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
 
     output_color = max(output_color, vec3(0.0));
 
-    textureStore(normal, vec2<i32>(in.position.xy), vec4(hit.normal, 0.0));
-    textureStore(position, vec2<i32>(in.position.xy), vec4(hit.reprojection_pos, 0.0));
+    // the attachments start at the top left of the camera viewport
+    let pixel = vec2<i32>(in.uv * resolution);
+    let depth = hit_depth(hit);
+    textureStore(normal, pixel, vec4(hit.normal, 0.0));
+    textureStore(position, pixel, vec4(hit.reprojection_pos, depth));
 
-    return vec4<f32>(output_color, 1.0);
+    return FragmentOutput(vec4<f32>(output_color, 1.0), depth);
 }
\ No newline at end of file
@@ -0,0 +1,175 @@
+use super::{
+    copy_outside_viewport, set_viewport, TracePipelineData, TraceSettings, ViewTraceUniformBuffer,
+};
+use crate::voxel_pipeline::RenderGraphSettings;
+use bevy::{
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{self, SlotInfo, SlotType},
+        render_resource::*,
+        view::{ExtractedView, ViewDepthTexture, ViewTarget},
+    },
+};
+
+/// Rebuilds the full resolution image when `TraceSettings::render_scale` is
+/// below 1
+pub struct TemporalUpscaleNode {
+    query: QueryState<
+        (
+            &'static ExtractedCamera,
+            &'static ViewTarget,
+            &'static ViewDepthTexture,
+            &'static ViewTraceUniformBuffer,
+            &'static TraceSettings,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl TemporalUpscaleNode {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: world.query_filtered(),
+        }
+    }
+}
+
+impl render_graph::Node for TemporalUpscaleNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![
+            SlotInfo::new("normal", SlotType::TextureView),
+            SlotInfo::new("position", SlotType::TextureView),
+            SlotInfo::new("last_normal", SlotType::TextureView),
+            SlotInfo::new("last_position", SlotType::TextureView),
+            SlotInfo::new("history", SlotType::TextureView),
+        ]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut bevy::render::renderer::RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let view_entity = graph.view_entity();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let trace_pipeline_data = world.resource::<TracePipelineData>();
+        let render_graph_settings = world.resource::<RenderGraphSettings>();
+
+        let (camera, target, depth, trace_uniform_buffer, trace_settings) =
+            match self.query.get_manual(world, view_entity) {
+                Ok(result) => result,
+                Err(_) => return Ok(()),
+            };
+
+        if !render_graph_settings.trace || !trace_settings.upscales() {
+            return Ok(());
+        }
+
+        let (upscale_pipeline, history_store_pipeline) = match (
+            pipeline_cache.get_render_pipeline(trace_pipeline_data.upscale_pipeline_id),
+            pipeline_cache.get_render_pipeline(trace_pipeline_data.history_store_pipeline_id),
+        ) {
+            (Some(upscale_pipeline), Some(history_store_pipeline)) => {
+                (upscale_pipeline, history_store_pipeline)
+            }
+            _ => return Ok(()),
+        };
+
+        let normal = graph.get_input_texture("normal")?;
+        let position = graph.get_input_texture("position")?;
+        let last_normal = graph.get_input_texture("last_normal")?;
+        let last_position = graph.get_input_texture("last_position")?;
+        let history = graph.get_input_texture("history")?;
+
+        let upscale_bind_group = render_context.render_device().create_bind_group(
+            None,
+            &trace_pipeline_data.accumulation_bind_group_layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: trace_uniform_buffer.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(history),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(normal),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(position),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(last_normal),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(last_position),
+                },
+            ],
+        );
+
+        // upscale into the whole image, then keep it as the next history
+        for (label, pipeline, depth_stencil_attachment) in [
+            (
+                "temporal upscale pass",
+                upscale_pipeline,
+                Some(RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            ),
+            ("history store pass", history_store_pipeline, None),
+        ] {
+            let post_process = target.post_process_write();
+            copy_outside_viewport(render_context, world, camera, &post_process);
+
+            let color_bind_group = render_context.render_device().create_bind_group(
+                None,
+                &trace_pipeline_data.color_bind_group_layout,
+                &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(post_process.source),
+                }],
+            );
+
+            let mut render_pass =
+                render_context
+                    .command_encoder()
+                    .begin_render_pass(&RenderPassDescriptor {
+                        label: Some(label),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: post_process.destination,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Load,
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment,
+                    });
+
+            render_pass.set_bind_group(0, &upscale_bind_group, &[]);
+            render_pass.set_bind_group(1, &color_bind_group, &[]);
+            set_viewport(&mut render_pass, &camera.viewport);
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_voxel_engine::common::{TraceUniforms, same_surface}
+
+@group(0) @binding(0)
+var<uniform> trace_uniforms: TraceUniforms;
+@group(0) @binding(1)
+var history: texture_storage_2d<rgba16float, read_write>;
+@group(0) @binding(2)
+var normal: texture_storage_2d<rgba16float, read_write>;
+@group(0) @binding(3)
+var position: texture_storage_2d<rgba32float, read_write>;
+@group(0) @binding(4)
+var last_normal: texture_storage_2d<rgba16float, read_write>;
+@group(0) @binding(5)
+var last_position: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(0)
+var color: texture_2d<f32>;
+
+// how much of the traced frame is blended into the history, traced rays that
+// landed close to the center of the pixel count for more
+const MIN_BLEND = 0.05;
+const MAX_BLEND = 0.4;
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+// Rebuilds the full resolution image from the jittered lower resolution trace
+// and the history of the last frames
+@fragment
+fn upscale(in: FullscreenVertexOutput) -> FragmentOutput {
+    let render_size = vec2<i32>(textureDimensions(position));
+    let output_size = vec2<f32>(textureDimensions(history));
+
+    // the traced pixel whose ray landed closest to this pixel
+    let render_pos = in.uv * vec2<f32>(render_size) - trace_uniforms.jitter;
+    let render_pixel = clamp(vec2<i32>(render_pos), vec2(0), render_size - 1);
+    // the traced colour is at the top left of the camera viewport
+    let viewport_offset = vec2<i32>(in.position.xy) - vec2<i32>(in.uv * output_size);
+    let current_color = textureLoad(color, render_pixel + viewport_offset, 0).rgb;
+    let current_position = textureLoad(position, render_pixel);
+
+    // the history is kept inside the colours around the traced pixel to stop
+    // it from ghosting
+    var color_min = current_color;
+    var color_max = current_color;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = clamp(render_pixel + vec2(x, y), vec2(0), render_size - 1);
+            let neighbor_color = textureLoad(color, neighbor + viewport_offset, 0).rgb;
+            color_min = min(color_min, neighbor_color);
+            color_max = max(color_max, neighbor_color);
+        }
+    }
+
+    var output: FragmentOutput;
+    output.depth = current_position.w;
+    output.color = vec4(current_color, 1.0);
+
+    // the sky has no position to reproject
+    if current_position.w == 0.0 {
+        return output;
+    }
+
+    let last_clip_oweigh = trace_uniforms.last_camera * vec4(current_position.xyz, 1.0);
+    let last_clip = vec2(1.0, -1.0) * last_clip_oweigh.xy / last_clip_oweigh.w;
+    if last_clip_oweigh.w <= 0.0 || any(last_clip <= vec2(-1.0)) || any(last_clip >= vec2(1.0)) {
+        return output;
+    }
+
+    // only reuse the history if it was on the same surface last frame, the
+    // surfaces are kept at the traced resolution
+    let last_uv = last_clip * 0.5 + 0.5;
+    let last_render_pixel = clamp(vec2<i32>(last_uv * vec2<f32>(render_size)), vec2(0), render_size - 1);
+    let current_normal = textureLoad(normal, render_pixel).xyz;
+    let history_normal = textureLoad(last_normal, last_render_pixel).xyz;
+    let history_position = textureLoad(last_position, last_render_pixel).xyz;
+    if !same_surface(current_normal, current_position.xyz, history_normal, history_position) {
+        return output;
+    }
+
+    let last_pixel = vec2<i32>(last_uv * output_size);
+    let last_color = clamp(textureLoad(history, last_pixel).rgb, color_min, color_max);
+
+    let distance = length(render_pos - (vec2<f32>(render_pixel) + 0.5));
+    let blend = mix(MAX_BLEND, MIN_BLEND, saturate(distance * sqrt(2.0)));
+    output.color = vec4(mix(last_color, current_color, blend), 1.0);
+
+    return output;
+}
+
+// Keeps the upscaled frame around for the next frame
+@fragment
+fn store_history(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.uv * vec2<f32>(textureDimensions(history)));
+    let upscaled = textureLoad(color, vec2<i32>(in.position.xy), 0);
+    textureStore(history, pixel, upscaled);
+    return upscaled;
+}
@@ -38,7 +38,7 @@ impl Plugin for VoxelWorldPlugin {
         // Uniforms
         let voxel_uniforms = VoxelUniforms {
             pallete: gh.pallete.into(),
-            portals: [ExtractedPortal::default(); 32],
+            materials: gh.materials,
             levels,
             offsets,
             texture_size,
@@ -46,6 +46,10 @@ impl Plugin for VoxelWorldPlugin {
         let mut uniform_buffer = UniformBuffer::from(voxel_uniforms.clone());
         uniform_buffer.write_buffer(&render_device, &render_queue);
 
+        let voxel_portals = VoxelPortals::default();
+        let mut portal_buffer = StorageBuffer::from(voxel_portals.clone());
+        portal_buffer.write_buffer(&render_device, &render_queue);
+
         // Texture
         let voxel_world = render_device.create_texture_with_data(
             &render_queue,
@@ -74,23 +78,13 @@ impl Plugin for VoxelWorldPlugin {
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
         });
 
-        // Mip texture
-        let mip_count = gh.texture_size.trailing_zeros();
-        let mip_texture = render_device.create_texture(&TextureDescriptor {
-            label: None,
-            size: Extent3d {
-                width: gh.texture_size,
-                height: gh.texture_size,
-                depth_or_array_layers: gh.texture_size,
-            },
-            mip_level_count: mip_count,
-            sample_count: 1,
-            dimension: TextureDimension::D3,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
+        // Mip textures
+        let mip_texture =
+            create_mip_texture(render_device, gh.texture_size, TextureFormat::Rgba8Unorm);
         let mip_texture_view = mip_texture.create_view(&TextureViewDescriptor::default());
+        let mip_material_texture =
+            create_mip_texture(render_device, gh.texture_size, TextureFormat::R16Uint);
+        let mip_material_view = mip_material_texture.create_view(&TextureViewDescriptor::default());
 
         // Sampler
         let texture_sampler = render_device.create_sampler(&SamplerDescriptor {
@@ -150,6 +144,26 @@ impl Plugin for VoxelWorldPlugin {
                         ty: BindingType::Sampler(SamplerBindingType::Filtering),
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(VoxelPortals::SHADER_SIZE.into()),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Uint,
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -177,14 +191,24 @@ impl Plugin for VoxelWorldPlugin {
                     binding: 4,
                     resource: BindingResource::Sampler(&texture_sampler),
                 },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: portal_buffer.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::TextureView(&mip_material_view),
+                },
             ],
         );
 
         app.insert_resource(LoadVoxelWorld::None)
             .insert_resource(NewGH::None)
             .insert_resource(voxel_uniforms)
+            .insert_resource(voxel_portals)
             .add_plugins(ExtractResourcePlugin::<NewGH>::default())
             .add_plugins(ExtractResourcePlugin::<VoxelUniforms>::default())
+            .add_plugins(ExtractResourcePlugin::<VoxelPortals>::default())
             .add_systems(Update, load_voxel_world);
 
         let render_app = app.sub_app_mut(RenderApp);
@@ -192,9 +216,11 @@ impl Plugin for VoxelWorldPlugin {
         render_app
             .insert_resource(VoxelData {
                 uniform_buffer,
+                portal_buffer,
                 voxel_world,
                 grid_hierarchy,
                 mip_texture,
+                mip_material_texture,
                 texture_sampler,
                 bind_group_layout,
                 bind_group,
@@ -208,9 +234,13 @@ impl Plugin for VoxelWorldPlugin {
 #[derive(Resource)]
 pub struct VoxelData {
     pub uniform_buffer: UniformBuffer<VoxelUniforms>,
+    pub portal_buffer: StorageBuffer<VoxelPortals>,
     pub voxel_world: TextureView,
     pub grid_hierarchy: Buffer,
+    /// averaged colours of the world, the alpha is how much of a cell is filled
     pub mip_texture: Texture,
+    /// the most common material and flags in every cell of the mips
+    pub mip_material_texture: Texture,
     pub texture_sampler: Sampler,
     pub bind_group_layout: BindGroupLayout,
     pub bind_group: BindGroup,
@@ -231,6 +261,35 @@ impl Into<[PalleteEntry; 256]> for Pallete {
     }
 }
 
+/// Surface properties of a pallete entry, the colour stays in the pallete
+#[derive(Debug, Clone, Copy, ShaderType)]
+pub struct VoxelMaterial {
+    /// 0 is a perfect mirror, 1 only scatters light diffusely
+    pub roughness: f32,
+    /// metals tint their reflections with the colour and have no diffuse light
+    pub metallic: f32,
+    /// how strongly the colour glows, 0 gives off no light
+    pub emission: f32,
+    /// how much light passes through the voxel instead of scattering off it
+    pub transparency: f32,
+    /// index of refraction, controls how much light is reflected at steep angles
+    // padded so the stride in the uniform buffer is a multiple of 16
+    #[size(16)]
+    pub ior: f32,
+}
+
+impl Default for VoxelMaterial {
+    fn default() -> Self {
+        Self {
+            roughness: 1.0,
+            metallic: 0.0,
+            emission: 0.0,
+            transparency: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy, ShaderType)]
 pub struct ExtractedPortal {
     pub transformation: Mat4,
@@ -238,23 +297,52 @@ pub struct ExtractedPortal {
     pub normal: Vec3,
 }
 
+impl ExtractedPortal {
+    /// A zero normal marks a slot that rays and physics go straight through
+    pub const DISABLED: Self = Self {
+        transformation: Mat4::IDENTITY,
+        position: Vec3::ZERO,
+        normal: Vec3::ZERO,
+    };
+}
+
+/// Portals are told apart by the material byte of their voxels, so there can't
+/// be more than one per material
+pub const MAX_PORTALS: usize = 256;
+
+/// The portal table, too large for the uniform buffer so it is bound as a
+/// storage buffer
+#[derive(Resource, ExtractResource, Clone, ShaderType)]
+pub struct VoxelPortals {
+    pub portals: [ExtractedPortal; MAX_PORTALS],
+}
+
+impl Default for VoxelPortals {
+    fn default() -> Self {
+        Self {
+            portals: [ExtractedPortal::DISABLED; MAX_PORTALS],
+        }
+    }
+}
+
 #[derive(Resource, ExtractResource, Clone, ShaderType)]
 pub struct VoxelUniforms {
     pub pallete: [PalleteEntry; 256],
-    pub portals: [ExtractedPortal; 32],
+    pub materials: [VoxelMaterial; 256],
     pub levels: [UVec4; 8],
     pub offsets: [UVec4; 8],
     pub texture_size: u32,
 }
 
 #[derive(Resource, ExtractResource, Clone)]
-enum NewGH {
+pub(crate) enum NewGH {
     Some(Arc<GH>),
     None,
 }
 
 fn prepare_uniforms(
     voxel_uniforms: Res<VoxelUniforms>,
+    voxel_portals: Res<VoxelPortals>,
     mut voxel_data: ResMut<VoxelData>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
@@ -263,6 +351,11 @@ fn prepare_uniforms(
     voxel_data
         .uniform_buffer
         .write_buffer(&render_device, &render_queue);
+
+    voxel_data.portal_buffer.set(voxel_portals.clone());
+    voxel_data
+        .portal_buffer
+        .write_buffer(&render_device, &render_queue);
 }
 
 fn load_voxel_world(
@@ -287,6 +380,7 @@ fn load_voxel_world(
             }
 
             voxel_uniforms.pallete = gh.pallete.clone().into();
+            voxel_uniforms.materials = gh.materials;
             voxel_uniforms.levels = levels;
             voxel_uniforms.texture_size = gh.texture_size;
 
@@ -336,26 +430,36 @@ fn load_voxel_world_prepare(
         );
         voxel_data.voxel_world = voxel_world.create_view(&TextureViewDescriptor::default());
 
-        // mip texture
-        let mip_count = gh.texture_size.trailing_zeros();
-        let mip_texture = render_device.create_texture(&TextureDescriptor {
-            label: None,
-            size: Extent3d {
-                width: gh.texture_size,
-                height: gh.texture_size,
-                depth_or_array_layers: gh.texture_size,
-            },
-            mip_level_count: mip_count,
-            sample_count: 1,
-            dimension: TextureDimension::D3,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
-        voxel_data.mip_texture = mip_texture;
+        // mip textures
+        voxel_data.mip_texture =
+            create_mip_texture(&render_device, gh.texture_size, TextureFormat::Rgba8Unorm);
+        voxel_data.mip_material_texture =
+            create_mip_texture(&render_device, gh.texture_size, TextureFormat::R16Uint);
     }
 }
 
+// every mip down to 2x2x2 cells, filled by the mip pass
+fn create_mip_texture(
+    render_device: &RenderDevice,
+    texture_size: u32,
+    format: TextureFormat,
+) -> Texture {
+    render_device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: texture_size,
+            height: texture_size,
+            depth_or_array_layers: texture_size,
+        },
+        mip_level_count: texture_size.trailing_zeros(),
+        sample_count: 1,
+        dimension: TextureDimension::D3,
+        format,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
 fn queue_bind_group(render_device: Res<RenderDevice>, mut voxel_data: ResMut<VoxelData>) {
     let bind_group = render_device.create_bind_group(
         None,
@@ -385,6 +489,18 @@ fn queue_bind_group(render_device: Res<RenderDevice>, mut voxel_data: ResMut<Vox
                 binding: 4,
                 resource: BindingResource::Sampler(&voxel_data.texture_sampler),
             },
+            BindGroupEntry {
+                binding: 5,
+                resource: voxel_data.portal_buffer.binding().unwrap(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(
+                    &voxel_data
+                        .mip_material_texture
+                        .create_view(&TextureViewDescriptor::default()),
+                ),
+            },
         ],
     );
     voxel_data.bind_group = bind_group;
@@ -45,6 +45,8 @@ impl Plugin for VoxelizationPlugin {
         );
 
         app.add_plugins(ExtractComponentPlugin::<VoxelizationMaterial>::default())
+            .add_plugins(ExtractComponentPlugin::<PortalIndex>::default())
+            .add_plugins(ExtractComponentPlugin::<VoxelizationCamera>::default())
             .add_systems(Startup, setup)
             .add_systems(Update, update_cameras);
     }
@@ -68,7 +70,7 @@ impl Plugin for VoxelizationPlugin {
 #[derive(Resource, Deref, DerefMut)]
 struct VoxelizationImage(Handle<Image>);
 
-#[derive(Component)]
+#[derive(Component, Clone, ExtractComponent)]
 struct VoxelizationCamera;
 
 fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
@@ -183,6 +185,11 @@ impl Default for VoxelizationMaterial {
     }
 }
 
+/// Index of a `Portal` in `VoxelPortals`, the voxels of the portal are written
+/// with it in place of their material
+#[derive(Component, Clone, Copy, ExtractComponent)]
+pub(crate) struct PortalIndex(pub u8);
+
 #[derive(Clone)]
 pub enum VoxelizationMaterialType {
     Texture(Handle<Image>),
@@ -286,7 +293,7 @@ impl SpecializedMeshPipeline for VoxelizationPipeline {
         descriptor.vertex.shader = VOXELIZATION_SHADER_HANDLE;
         descriptor.fragment.as_mut().unwrap().shader = VOXELIZATION_SHADER_HANDLE;
 
-        // 
+        //
         descriptor
             .vertex
             .shader_defs
@@ -313,7 +320,7 @@ fn queue_custom(
     render_meshes: Res<RenderAssets<Mesh>>,
     material_meshes: Query<Entity, With<VoxelizationMaterial>>,
     render_mesh_instances: Res<RenderMeshInstances>,
-    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>), With<VoxelizationCamera>>,
     render_graph_settings: Res<RenderGraphSettings>,
 ) {
     if !render_graph_settings.voxelization {
@@ -364,20 +371,23 @@ fn queue_bind_group(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
-    voxelization_materials: Query<(Entity, &VoxelizationMaterial)>,
+    voxelization_materials: Query<(Entity, &VoxelizationMaterial, Option<&PortalIndex>)>,
     gpu_images: Res<RenderAssets<Image>>,
     voxelization_pipeline: Res<VoxelizationPipeline>,
     fallback_images: Res<FallbackImage>,
     mut voxelization_uniforms: ResMut<VoxelizationUniformsResource>,
 ) {
-    for (entity, voxelization_material) in voxelization_materials.iter() {
+    for (entity, voxelization_material, portal_index) in voxelization_materials.iter() {
+        let mut value = VoxelizationUniforms::from(voxelization_material);
+        if let Some(portal_index) = portal_index {
+            value.material = portal_index.0 as u32;
+        }
+
         let uniforms = voxelization_uniforms
             .entry(entity)
-            .or_insert(UniformBuffer::from(VoxelizationUniforms::from(
-                voxelization_material,
-            )));
+            .or_insert(UniformBuffer::from(value.clone()));
 
-        uniforms.set(voxelization_material.into());
+        uniforms.set(value);
         uniforms.write_buffer(&render_device, &render_queue);
 
         let sampler = render_device.create_sampler(&SamplerDescriptor::default());